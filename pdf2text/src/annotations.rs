@@ -0,0 +1,142 @@
+use pathfinder_geometry::{rect::RectF, transform2d::Transform2F, vector::Vector2F};
+use pdf::object::{PlainRef, Resolve};
+use pdf::primitive::{Dictionary, Primitive};
+use pdf_render::text_string;
+
+use crate::objects::{dict, entry, rect, resolved};
+use crate::outline::Destinations;
+
+// a comment, highlight or other markup with its text
+pub struct Annotation {
+    pub subtype: String,
+    // in device space, like the spans
+    pub rect: RectF,
+    pub contents: String,
+}
+
+// the annotation dictionaries of a page
+pub fn annots(resolve: &impl Resolve, page: PlainRef) -> Vec<Dictionary> {
+    let page = match resolve.resolve(page) {
+        Ok(Primitive::Dictionary(page)) => page,
+        _ => return vec![],
+    };
+    match page.get("Annots").and_then(|p| resolved(resolve, p)) {
+        Some(Primitive::Array(annots)) => annots.iter().filter_map(|a| dict(resolve, a)).collect(),
+        _ => vec![],
+    }
+}
+
+fn subtype(annot: &Dictionary) -> &str {
+    match annot.get("Subtype") {
+        Some(Primitive::Name(name)) => name.as_str(),
+        _ => "",
+    }
+}
+
+// the /Rect of an annotation in user space
+fn annot_rect(resolve: &impl Resolve, annot: &Dictionary) -> Option<RectF> {
+    let [x0, y0, x1, y1] = annot.get("Rect").and_then(|p| rect(resolve, p))?;
+    Some(RectF::from_points(
+        Vector2F::new(x0.min(x1), y0.min(y1)),
+        Vector2F::new(x0.max(x1), y0.max(y1)),
+    ))
+}
+
+// the annotations of a page that have /Contents, `transform` maps user space to device space
+pub fn page_annotations(resolve: &impl Resolve, page: PlainRef, transform: Transform2F) -> Vec<Annotation> {
+    annots(resolve, page).iter()
+        // a popup only shows the contents of its parent
+        .filter(|annot| subtype(annot) != "Popup")
+        .filter_map(|annot| {
+            let contents = match annot.get("Contents").and_then(|p| resolved(resolve, p)) {
+                Some(Primitive::String(s)) => text_string(s.as_bytes()),
+                _ => return None,
+            };
+            Some(Annotation {
+                subtype: subtype(annot).into(),
+                rect: annot_rect(resolve, annot).map_or(RectF::default(), |rect| transform * rect),
+                contents,
+            })
+        })
+        .filter(|annot| !annot.contents.is_empty())
+        .collect()
+}
+
+pub enum LinkTarget {
+    Uri(String),
+    // index of a page in this document
+    Page(usize),
+}
+
+pub struct Link {
+    pub target: LinkTarget,
+    // in device space, like the spans
+    pub rect: RectF,
+}
+
+// the links of a page, `transform` maps user space to device space
+pub fn page_links(resolve: &impl Resolve, page: PlainRef, dests: &Destinations, transform: Transform2F) -> Vec<Link> {
+    annots(resolve, page).iter()
+        .filter(|annot| subtype(annot) == "Link")
+        .filter_map(|annot| {
+            let uri = entry(resolve, annot, "A")
+                .filter(|action| matches!(action.get("S"), Some(Primitive::Name(s)) if s.as_str() == "URI"))
+                .and_then(|action| match action.get("URI").and_then(|p| resolved(resolve, p)) {
+                    Some(Primitive::String(s)) => Some(String::from_utf8_lossy(s.as_bytes()).into_owned()),
+                    _ => None,
+                });
+            let target = match uri {
+                Some(uri) => LinkTarget::Uri(uri),
+                None => LinkTarget::Page(dests.target(resolve, annot)?),
+            };
+            let rect = annot_rect(resolve, annot)?;
+            Some(Link { target, rect: transform * rect })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdf::file::File;
+    use pdf_render::testpdf;
+
+    #[test]
+    fn highlight_with_a_popup() {
+        let data = testpdf::pdf_with_page(b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Annots [4 0 R 5 0 R] >>", &[
+            b"<< /Type /Annot /Subtype /Highlight /Rect [10 150 90 140] /Contents <FEFF0043006800650063006B0020201C0074006800690073201D> /Popup 5 0 R >>",
+            b"<< /Type /Annot /Subtype /Popup /Rect [100 100 200 150] /Contents (Check this) /Parent 4 0 R >>",
+        ]);
+        let file = File::from_data(data).unwrap();
+        let annots = page_annotations(&file, PlainRef { id: 3, gen: 0 }, Transform2F::from_scale(0.5));
+        // the comment only once, in device space
+        assert_eq!(annots.len(), 1);
+        assert_eq!(annots[0].subtype, "Highlight");
+        assert_eq!(annots[0].contents, "Check \u{201C}this\u{201D}");
+        assert_eq!(annots[0].rect, RectF::from_points(Vector2F::new(5., 70.), Vector2F::new(45., 75.)));
+    }
+
+    #[test]
+    fn external_and_internal_links() {
+        let data = testpdf::pdf_with_objects(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>",
+            b"<< /Type /Pages /Kids [3 0 R 4 0 R] /Count 2 >>",
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Annots [5 0 R 6 0 R 7 0 R] >>",
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] >>",
+            b"<< /Type /Annot /Subtype /Link /Rect [10 10 50 20] /A << /S /URI /URI (https://example.com/) >> >>",
+            b"<< /Type /Annot /Subtype /Link /Rect [10 30 50 40] /Dest [4 0 R /Fit] >>",
+            b"<< /Type /Annot /Subtype /Text /Rect [0 0 10 10] /Contents (not a link) >>",
+        ]);
+        let file = File::from_data(data).unwrap();
+        let dests = Destinations::new(&file, &crate::objects::catalog(&file).unwrap());
+        let links = page_links(&file, PlainRef { id: 3, gen: 0 }, &dests, Transform2F::from_scale(0.5));
+        let links: Vec<_> = links.iter().map(|link| match link.target {
+            LinkTarget::Uri(ref uri) => (uri.clone(), link.rect),
+            LinkTarget::Page(page) => (format!("page {}", page), link.rect),
+        }).collect();
+        assert_eq!(links, [
+            ("https://example.com/".into(), RectF::from_points(Vector2F::new(5., 5.), Vector2F::new(25., 10.))),
+            ("page 1".into(), RectF::from_points(Vector2F::new(5., 15.), Vector2F::new(25., 20.))),
+        ]);
+    }
+}
@@ -0,0 +1,52 @@
+use unicode_bidi::{bidi_class, BidiClass, BidiInfo};
+
+// Text is read in the order it appears on the page, left to right, which reverses
+// right-to-left runs. Reordering such a line the way the bidi algorithm does for display
+// reverses them back, while numbers and left-to-right words within them keep their order.
+pub fn logical_order(text: &str) -> String {
+    text.split('\n')
+        .map(|line| {
+            if !line.chars().any(|c| matches!(bidi_class(c), BidiClass::R | BidiClass::AL)) {
+                return line.to_string();
+            }
+            let info = BidiInfo::new(line, None);
+            info.paragraphs.iter()
+                .map(|para| info.reorder_line(para, para.range.clone()))
+                .collect()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::span;
+    use crate::{items2text, PageTextOptions};
+
+    #[test]
+    fn arabic() {
+        // "hello world", drawn from right to left
+        let logical = "\u{645}\u{631}\u{62D}\u{628}\u{627} \u{628}\u{627}\u{644}\u{639}\u{627}\u{644}\u{645}";
+        let visual: String = logical.chars().rev().collect();
+        assert_eq!(logical_order(&format!("Hello\n{}", visual)), format!("Hello\n{}", logical));
+        // "number 123", the digits on the page already read left to right
+        assert_eq!(logical_order("123 \u{62F}\u{62F}\u{639}\u{644}\u{627}"), "\u{627}\u{644}\u{639}\u{62F}\u{62F} 123");
+    }
+
+    #[test]
+    fn arabic_spans() {
+        // "hello world" drawn from the right: the glyphs of hello one at a time, each left of
+        // the one before it, and world as a whole, its glyphs in visual order
+        let (hello, world) = ("\u{645}\u{631}\u{62D}\u{628}\u{627}", "\u{628}\u{627}\u{644}\u{639}\u{627}\u{644}\u{645}");
+        let visual = |word: &str| word.chars().rev().collect::<String>();
+        let mut spans: Vec<_> = hello.chars().enumerate()
+            .map(|(i, c)| span(&c.to_string(), 38. - 2. * i as f32, 20., 4.))
+            .collect();
+        spans.push(span(&visual(world), 14., 20., 4.));
+        // the rows are read by x, so the gaps are the same whichever way the text advanced
+        let text = items2text(&spans, &PageTextOptions::default());
+        assert_eq!(text, format!("{} {}", visual(world), visual(hello)));
+        assert_eq!(logical_order(&text), format!("{} {}", hello, world));
+    }
+}
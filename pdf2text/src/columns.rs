@@ -0,0 +1,118 @@
+use pathfinder_geometry::rect::RectF;
+
+// Split the items into columns at the vertical gutters that (almost) no text crosses,
+// left to right. `height` is the size of the body text, a gutter has to be at least that
+// wide. With a `count`, the widest gaps are taken regardless of their width.
+pub fn split_columns<T>(items: Vec<(RectF, T)>, height: f32, count: Option<usize>) -> Vec<Vec<(RectF, T)>> {
+    let bounds = match boundaries(&items, height, count) {
+        Some(bounds) if !bounds.is_empty() => bounds,
+        _ => return vec![items],
+    };
+    let mut columns: Vec<Vec<(RectF, T)>> = (0 ..= bounds.len()).map(|_| vec![]).collect();
+    for (rect, item) in items {
+        // spans that cross a gutter, like a heading over both columns, go with their start
+        let column = bounds.iter().take_while(|&&x| x <= rect.min_x()).count();
+        columns[column].push((rect, item));
+    }
+    columns.retain(|column| !column.is_empty());
+    columns
+}
+
+// the x positions of the gutters, sorted
+fn boundaries<T>(items: &[(RectF, T)], height: f32, count: Option<usize>) -> Option<Vec<f32>> {
+    if matches!(count, Some(0 | 1)) || items.is_empty() || height <= 0. {
+        return None;
+    }
+    let min_x = items.iter().map(|(r, _)| r.min_x()).fold(f32::INFINITY, f32::min);
+    let max_x = items.iter().map(|(r, _)| r.max_x()).fold(f32::NEG_INFINITY, f32::max);
+    let step = height / 2.;
+    let n = ((max_x - min_x) / step).ceil() as usize;
+    if n < 3 {
+        return None;
+    }
+    let bin = |x: f32| (((x - min_x) / step) as usize).min(n - 1);
+
+    // how many spans cover each bin
+    let mut coverage = vec![0usize; n];
+    for (rect, _) in items {
+        for c in &mut coverage[bin(rect.min_x()) ..= bin(rect.max_x())] {
+            *c += 1;
+        }
+    }
+
+    // runs of bins between the first and last one that few spans cross
+    let threshold = items.len() / 50;
+    let mut gaps = vec![];
+    let mut start = None;
+    for (i, &c) in coverage.iter().enumerate().take(n - 1).skip(1) {
+        match (c <= threshold, start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                gaps.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        gaps.push((s, n - 1));
+    }
+
+    match count {
+        Some(count) => {
+            gaps.sort_by_key(|&(s, e)| std::cmp::Reverse(e - s));
+            gaps.truncate(count - 1);
+        }
+        None => gaps.retain(|&(s, e)| (e - s) as f32 * step >= height),
+    }
+    let mut bounds: Vec<f32> = gaps.iter()
+        .map(|&(s, e)| min_x + (s + e) as f32 / 2. * step)
+        .collect();
+    bounds.sort_by(f32::total_cmp);
+    Some(bounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pathfinder_geometry::vector::Vector2F;
+
+    fn rect(x0: f32, x1: f32, y: f32) -> RectF {
+        RectF::from_points(Vector2F::new(x0, y), Vector2F::new(x1, y + 4.))
+    }
+
+    // lines of 4mm text in a left and a right column, `gap` mm apart
+    fn two_columns(gap: f32) -> Vec<(RectF, &'static str)> {
+        (0 .. 30).flat_map(|i| {
+            let y = 20. + 5. * i as f32;
+            [(rect(10., 60., y), "left"), (rect(60. + gap, 110. + gap, y), "right")]
+        }).collect()
+    }
+
+    fn labels(columns: &[Vec<(RectF, &'static str)>]) -> Vec<Vec<&'static str>> {
+        columns.iter().map(|column| {
+            let mut labels: Vec<_> = column.iter().map(|&(_, label)| label).collect();
+            labels.dedup();
+            labels
+        }).collect()
+    }
+
+    #[test]
+    fn gutter() {
+        let mut items = two_columns(20.);
+        // a heading over both columns goes with the one it starts in
+        items.push((rect(10., 130., 10.), "heading"));
+        let columns = split_columns(items, 4., None);
+        assert_eq!(labels(&columns), [vec!["left", "heading"], vec!["right"]]);
+        assert_eq!(columns[0].len(), 31);
+    }
+
+    #[test]
+    fn narrow_gap() {
+        // narrower than the text is high, like the space between words
+        assert_eq!(split_columns(two_columns(6.), 8., None).len(), 1);
+        // unless the count asks for it
+        assert_eq!(labels(&split_columns(two_columns(6.), 8., Some(2))), [vec!["left"], vec!["right"]]);
+        assert_eq!(split_columns(two_columns(20.), 4., Some(1)).len(), 1);
+    }
+}
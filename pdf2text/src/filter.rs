@@ -0,0 +1,134 @@
+use pathfinder_geometry::rect::RectF;
+use pdf::content::TextMode;
+use pdf_render::TextSpan;
+
+use crate::lines::merge_spans;
+use crate::PageTextOptions;
+
+// The spans of a page that make it into the text, in the order they were drawn. `crop` and
+// `visible` are in device space, spans are kept when their origin is inside both.
+pub fn filter_spans(spans: Vec<TextSpan>, options: &PageTextOptions, crop: Option<RectF>, visible: Option<RectF>) -> Vec<TextSpan> {
+    let mut spans = spans
+        .into_iter()
+        .filter(|text| !(options.skip_invisible && matches!(text.mode, TextMode::Invisible)))
+        .filter(|text| !(options.skip_artifacts && text.artifact))
+        .filter(|text| crop.is_none_or(|crop| crop.contains_point(text.transform.translation())))
+        .filter(|text| visible.is_none_or(|visible| visible.contains_point(text.transform.translation())))
+        .filter(|text| options.min_confidence.is_none_or(|min| confidence(&text.text) >= min))
+        .collect::<Vec<_>>();
+    if options.dedup_overlapping {
+        spans.dedup_by(|span, prev| is_double_draw(prev, span));
+    }
+    if let Some(merge_gap) = options.merge_gap {
+        spans = merge_spans(spans, merge_gap, options.word_gap);
+    }
+    spans
+}
+
+// Fraction of the characters of `text` that look like text. A font without a usable
+// ToUnicode map tends to decode to control or private use characters, or to U+FFFD in
+// lossy mode. Whitespace doesn't count either way.
+fn confidence(text: &str) -> f32 {
+    let (mut good, mut total) = (0, 0);
+    for c in text.chars().filter(|c| !c.is_whitespace()) {
+        total += 1;
+        let private_use = matches!(c, '\u{E000}'..='\u{F8FF}' | '\u{F0000}'..='\u{FFFFD}' | '\u{100000}'..='\u{10FFFD}');
+        if !(c.is_control() || private_use || c == char::REPLACEMENT_CHARACTER) {
+            good += 1;
+        }
+    }
+    match total {
+        0 => 1.,
+        _ => good as f32 / total as f32,
+    }
+}
+
+// origins closer than this fraction of the font size count as the same place
+const DOUBLE_DRAW_TOLERANCE: f32 = 0.1;
+
+// Text drawn again right over itself, usually slightly offset to fake bold. Repeated letters
+// ("aa") are drawn an advance apart, which is much further.
+fn is_double_draw(prev: &TextSpan, span: &TextSpan) -> bool {
+    let tolerance = DOUBLE_DRAW_TOLERANCE * span.device_font_size();
+    let offset = span.transform.translation() - prev.transform.translation();
+    !span.text.trim().is_empty() && span.text == prev.text && offset.x().abs() <= tolerance && offset.y().abs() <= tolerance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pathfinder_geometry::vector::Vector2F;
+    use crate::tests::span;
+
+    fn invisible(text: &str, x: f32, baseline: f32, size: f32) -> TextSpan {
+        TextSpan { mode: TextMode::Invisible, ..span(text, x, baseline, size) }
+    }
+
+    fn text(spans: &[TextSpan]) -> Vec<&str> {
+        spans.iter().map(|span| span.text.as_str()).collect()
+    }
+
+    #[test]
+    fn ocr_layer() {
+        // a scan has nothing but the invisible text over its image
+        let spans = || vec![invisible("Scanned", 10., 20., 4.), invisible("page", 30., 20., 4.)];
+        let options = PageTextOptions::default();
+        assert_eq!(text(&filter_spans(spans(), &options, None, None)), ["Scanned", "page"]);
+
+        let options = PageTextOptions { skip_invisible: true, ..options };
+        assert!(filter_spans(spans(), &options, None, None).is_empty());
+    }
+
+    #[test]
+    fn watermark() {
+        let spans = || vec![span("Body", 10., 20., 4.), invisible("DRAFT", 10., 100., 40.), span("text", 10., 25., 4.)];
+        let options = PageTextOptions::default();
+        assert_eq!(text(&filter_spans(spans(), &options, None, None)), ["Body", "DRAFT", "text"]);
+
+        let options = PageTextOptions { skip_invisible: true, ..options };
+        assert_eq!(text(&filter_spans(spans(), &options, None, None)), ["Body", "text"]);
+    }
+
+    #[test]
+    fn running_header() {
+        let spans = || vec![TextSpan { artifact: true, ..span("Header", 10., 10., 4.) }, span("Body", 10., 30., 4.)];
+        let options = PageTextOptions::default();
+        assert_eq!(text(&filter_spans(spans(), &options, None, None)), ["Header", "Body"]);
+
+        let options = PageTextOptions { skip_artifacts: true, ..options };
+        assert_eq!(text(&filter_spans(spans(), &options, None, None)), ["Body"]);
+    }
+
+    #[test]
+    fn fake_bold() {
+        // the heading drawn twice, a little offset, and letters drawn one by one
+        let spans = || vec![
+            span("Heading", 10., 20., 8.),
+            span("Heading", 10.2, 20.1, 8.),
+            span("a", 10., 30., 4.),
+            span("a", 12., 30., 4.),
+        ];
+        let options = PageTextOptions::default();
+        assert_eq!(text(&filter_spans(spans(), &options, None, None)), ["Heading", "Heading", "a", "a"]);
+
+        let options = PageTextOptions { dedup_overlapping: true, ..options };
+        assert_eq!(text(&filter_spans(spans(), &options, None, None)), ["Heading", "a", "a"]);
+    }
+
+    #[test]
+    fn crop() {
+        // the total box of an invoice, only spans that start in it are kept
+        let spans = vec![span("Total", 10., 20., 4.), span("42.00", 60., 20., 4.), span("Due", 60., 40., 4.)];
+        let total = RectF::from_points(Vector2F::new(50., 10.), Vector2F::new(90., 30.));
+        assert_eq!(text(&filter_spans(spans, &PageTextOptions::default(), Some(total), None)), ["42.00"]);
+    }
+
+    #[test]
+    fn confidences() {
+        assert_eq!(confidence("Hello, world"), 1.);
+        assert_eq!(confidence("  "), 1.);
+        assert_eq!(confidence("\u{E000}\u{E001}"), 0.);
+        assert_eq!(confidence("ab\u{0001}\u{FFFD}"), 0.5);
+        assert_eq!(confidence("a \u{F0001}"), 0.5);
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+
+use pathfinder_geometry::{rect::RectF, vector::Vector2F};
+use pdf_render::TextSpan;
+
+// Lay the spans out on a fixed grid of `cell` sized characters, so that columns and
+// tables stay aligned. Each span starts in the cell under its left edge and its baseline;
+// where spans overlap, the one drawn first keeps the cell.
+pub fn layout_text(spans: &[TextSpan], view_box: RectF, cell: Vector2F) -> String {
+    let mut rows: BTreeMap<usize, Vec<Option<char>>> = BTreeMap::new();
+    for span in spans {
+        let x = ((span.rect.min_x() - view_box.min_x()) / cell.x()).round().max(0.) as usize;
+        let y = ((span.rect.max_y() - view_box.min_y()) / cell.y()).floor().max(0.) as usize;
+        let row = rows.entry(y).or_default();
+        for (i, c) in span.text.chars().enumerate() {
+            let col = x + i;
+            if row.len() <= col {
+                row.resize(col + 1, None);
+            }
+            if row[col].is_none() && !c.is_control() {
+                row[col] = Some(c);
+            }
+        }
+    }
+
+    let (first, last) = match (rows.keys().next(), rows.keys().next_back()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return String::new(),
+    };
+    let mut out = String::new();
+    for y in first ..= last {
+        if let Some(row) = rows.get(&y) {
+            let line: String = row.iter().map(|c| c.unwrap_or(' ')).collect();
+            out.push_str(line.trim_end());
+        }
+        if y != last {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::span;
+
+    // the spans of a table of two columns, the second at 20mm, with a 2mm advance
+    fn columns() -> Vec<TextSpan> {
+        vec![
+            span("ab", 0., 4., 4.), span("cd", 20., 4., 4.),
+            span("efgh", 0., 8., 4.), span("i", 20., 8., 4.),
+        ]
+    }
+
+    #[test]
+    fn aligned_columns() {
+        let view_box = RectF::new(Vector2F::zero(), Vector2F::new(100., 100.));
+        // a cell per char, and half as many across
+        assert_eq!(layout_text(&columns(), view_box, Vector2F::new(2., 4.)), "ab        cd\nefgh      i");
+        assert_eq!(layout_text(&columns(), view_box, Vector2F::new(4., 4.)), "ab   cd\nefgh i");
+    }
+
+    #[test]
+    fn overlapping_spans() {
+        // the second span starts in the third cell of the first, and only gets those after it
+        let spans = vec![span("first", 0., 4., 4.), span("XYZWVU", 4., 4., 4.)];
+        let view_box = RectF::new(Vector2F::zero(), Vector2F::new(100., 100.));
+        assert_eq!(layout_text(&spans, view_box, Vector2F::new(2., 4.)), "firstWVU");
+    }
+}
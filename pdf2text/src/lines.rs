@@ -0,0 +1,475 @@
+use std::sync::Arc;
+
+use pathfinder_geometry::rect::RectF;
+use pdf_render::{TextChar, TextSpan};
+
+use crate::{place_spans, PageTextOptions};
+
+// gaps between spans wider than this fraction of a space are word breaks
+pub const WORD_GAP: f32 = 0.5;
+
+pub struct TextLine<'a> {
+    // in reading order, left to right within a column
+    pub spans: Vec<&'a TextSpan>,
+    pub rect: RectF,
+    pub baseline: f32,
+}
+impl<'a> TextLine<'a> {
+    pub fn height(&self) -> f32 {
+        self.rect.height()
+    }
+    // the spans joined by a space where the gap exceeds `word_gap` spaces
+    pub fn text(&self, word_gap: f32) -> String {
+        let mut res = String::new();
+        let mut prev: Option<&TextSpan> = None;
+        for &span in self.spans.iter() {
+            if let Some(prev) = prev {
+                if span.rect.min_x() - prev.rect.max_x() > word_gap * word_space(prev, span) && !res.ends_with(' ') && !span.text.starts_with(' ')
+                    && !continues_cluster(&res, &span.text) {
+                    res.push(' ');
+                }
+            }
+            res += &span.text;
+            prev = Some(span);
+        }
+        res
+    }
+}
+
+pub struct Paragraph<'a> {
+    pub lines: Vec<TextLine<'a>>,
+    pub rect: RectF,
+}
+impl<'a> Paragraph<'a> {
+    // the lines reflowed into one
+    pub fn text(&self, dehyphenate: bool, word_gap: f32) -> String {
+        let mut res = String::new();
+        for line in self.lines.iter() {
+            let text = line.text(word_gap);
+            let text = text.trim();
+            if text.is_empty() {
+                continue;
+            }
+            if !res.is_empty() && (!dehyphenate || !join_hyphenated(&mut res, text)) {
+                res.push(' ');
+            }
+            res += text;
+        }
+        res
+    }
+}
+
+// The space to measure the gap from `prev` to `span` against. When the font changes, the
+// wider of the two: a symbol font used for one char in a word tends to have a narrow space
+// or none at all, which would break the word apart.
+pub fn word_space(prev: &TextSpan, span: &TextSpan) -> f32 {
+    match same_font(prev, span) {
+        true => span.space_width(),
+        false => span.space_width().max(prev.space_width()),
+    }
+}
+
+fn same_font(a: &TextSpan, b: &TextSpan) -> bool {
+    match (&a.font, &b.font) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+// Remove the hyphen from the end of `text` if the word continues in `next`, which is the
+// case when a letter precedes it and `next` starts in lower case. A compound that is broken
+// at its own hyphen ("well-" "known") can't be told apart and loses the hyphen as well.
+pub fn join_hyphenated(text: &mut String, next: &str) -> bool {
+    let mut chars = text.chars().rev();
+    let hyphen = matches!(chars.next(), Some('-' | '\u{AD}'));
+    let after_letter = chars.next().is_some_and(char::is_alphabetic);
+    let continues = next.trim_start().chars().next().is_some_and(char::is_lowercase);
+    if hyphen && after_letter && continues {
+        text.pop();
+        true
+    } else {
+        false
+    }
+}
+
+// Whether `next` starts with a combining mark or joiner that belongs to the end of `text`,
+// so that a space between them would split a character. Without unicode-segmentation
+// nothing is taken to continue.
+#[cfg(feature = "unicode-segmentation")]
+pub fn continues_cluster(text: &str, next: &str) -> bool {
+    use unicode_segmentation::UnicodeSegmentation;
+
+    let (last, first) = match (text.graphemes(true).next_back(), next.chars().next()) {
+        (Some(last), Some(first)) => (last, first),
+        _ => return false,
+    };
+    let mut joined = last.to_string();
+    joined.push(first);
+    joined.graphemes(true).count() == 1
+}
+
+#[cfg(not(feature = "unicode-segmentation"))]
+pub fn continues_cluster(_text: &str, _next: &str) -> bool {
+    false
+}
+
+// Collapse runs of spaces and tabs within each line to a single space and drop them at
+// the end of lines. Leading whitespace and the line breaks stay as they are.
+pub fn collapse_whitespace(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            res.push('\n');
+        }
+        let line = line.trim_end_matches([' ', '\t']);
+        let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+        res += &line[.. indent];
+        let mut space = false;
+        for c in line[indent ..].chars() {
+            if c == ' ' || c == '\t' {
+                space = true;
+                continue;
+            }
+            if space {
+                res.push(' ');
+                space = false;
+            }
+            res.push(c);
+        }
+    }
+    res
+}
+
+// Chinese, Japanese and Korean characters that are written without spaces between words,
+// leaving out their punctuation and the combining sound marks of kana
+fn is_ideograph(c: char) -> bool {
+    matches!(c,
+        '\u{3041}'..='\u{3096}' | '\u{30A1}'..='\u{30FA}' | '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}'
+        | '\u{AC00}'..='\u{D7AF}' | '\u{F900}'..='\u{FAFF}' | '\u{20000}'..='\u{2FA1F}')
+}
+
+// Put a space where ideographs meet letters or digits of other scripts, and with `every`
+// also between any two ideographs, so that each is a word of its own.
+pub fn space_ideographs(text: &str, every: bool) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut prev: Option<char> = None;
+    for c in text.chars() {
+        if let Some(prev) = prev {
+            let boundary = match (is_ideograph(prev), is_ideograph(c)) {
+                (true, true) => every,
+                (true, false) => c.is_alphanumeric(),
+                (false, true) => prev.is_alphanumeric(),
+                (false, false) => false,
+            };
+            if boundary {
+                res.push(' ');
+            }
+        }
+        res.push(c);
+        prev = Some(c);
+    }
+    res
+}
+
+// baselines this fraction of the font size apart count as the same when merging spans
+const MERGE_BASELINE_TOLERANCE: f32 = 0.1;
+
+// Merge spans drawn one after another on the same baseline, in the same font, size and
+// color, when the gap between them is at most `merge_gap` spaces. Gaps wider than
+// `word_gap` spaces become a space. Overprinting a little (kerning) still merges.
+pub fn merge_spans(spans: Vec<TextSpan>, merge_gap: f32, word_gap: f32) -> Vec<TextSpan> {
+    let mut merged: Vec<TextSpan> = Vec::with_capacity(spans.len());
+    for span in spans {
+        if let Some(prev) = merged.last_mut() {
+            if let Some(x) = continuation(prev, &span, merge_gap) {
+                append(prev, span, x, word_gap);
+                continue;
+            }
+        }
+        merged.push(span);
+    }
+    merged
+}
+
+// where `span` starts in the text space of `prev`, if it continues it
+fn continuation(prev: &TextSpan, span: &TextSpan, merge_gap: f32) -> Option<f32> {
+    let same_font = same_font(prev, span);
+    let (a, b) = (prev.transform, span.transform);
+    let same_matrix = a.m11() == b.m11() && a.m12() == b.m12() && a.m21() == b.m21() && a.m22() == b.m22();
+    if !same_font || !same_matrix || prev.font_size != span.font_size || prev.rise != span.rise
+        || prev.color != span.color || prev.mode != span.mode {
+        return None;
+    }
+    let start = prev.transform.inverse() * span.transform.translation();
+    let gap = start.x() - prev.width;
+    let tolerance = MERGE_BASELINE_TOLERANCE * prev.font_size;
+    if start.y().abs() > tolerance || gap < -tolerance || gap > merge_gap * prev.space_advance {
+        return None;
+    }
+    Some(start.x())
+}
+
+// add `span`, which starts at `x` in the text space of `prev`, to the end of it
+fn append(prev: &mut TextSpan, span: TextSpan, x: f32, word_gap: f32) {
+    let gap = x - prev.width;
+    if gap > word_gap * prev.space_advance && !prev.text.ends_with(' ') && !span.text.starts_with(' ')
+        && !continues_cluster(&prev.text, &span.text) {
+        prev.chars.push(TextChar { offset: prev.text.len(), pos: prev.width, width: gap });
+        prev.text.push(' ');
+    }
+    let offset = prev.text.len();
+    prev.chars.extend(span.chars.iter().map(|c| TextChar { offset: c.offset + offset, pos: c.pos + x, width: c.width }));
+    prev.text += &span.text;
+    prev.width = x + span.width;
+    prev.rect = prev.rect.union_rect(span.rect);
+    prev.font_rect = prev.font_rect.union_rect(span.font_rect);
+    prev.bbox = match (prev.bbox, span.bbox) {
+        (Some(a), Some(b)) => Some(a.union_rect(b)),
+        (a, b) => a.or(b),
+    };
+}
+
+// spans whose baselines are closer than this fraction of their height share a line
+const BASELINE_TOLERANCE: f32 = 0.3;
+
+// The lines of the page text, the spans that place_spans puts on one line in the order it
+// reads them. Columns, rotated text and dehyphenation come out as they do in the text.
+pub fn group_lines<'a>(spans: &'a [TextSpan], options: &PageTextOptions) -> Vec<TextLine<'a>> {
+    let (text, placements) = place_spans(spans, options);
+    let mut lines: Vec<TextLine> = vec![];
+    let mut prev_end = None;
+    for placement in placements {
+        let span = placement.span;
+        // a line break in the text between the span and the one before it
+        let new_line = prev_end.is_none_or(|end| text.get(end .. placement.range.start).is_some_and(|gap| gap.contains('\n')));
+        prev_end = Some(placement.range.end);
+        match lines.last_mut() {
+            Some(line) if !new_line => {
+                line.rect = line.rect.union_rect(span.rect);
+                line.spans.push(span);
+            }
+            _ => lines.push(TextLine {
+                spans: vec![span],
+                rect: span.rect,
+                baseline: span.rect.max_y(),
+            }),
+        }
+    }
+    lines
+}
+
+// The spans by baseline, top to bottom and left to right. Unlike group_lines this takes rows
+// across the columns, like a table has them.
+pub fn group_rows(spans: &[TextSpan]) -> Vec<TextLine<'_>> {
+    let mut spans: Vec<&TextSpan> = spans.iter().collect();
+    spans.sort_by(|a, b| a.rect.max_y().total_cmp(&b.rect.max_y()).then(a.rect.min_x().total_cmp(&b.rect.min_x())));
+
+    let mut lines: Vec<TextLine> = vec![];
+    for span in spans {
+        let baseline = span.rect.max_y();
+        match lines.last_mut() {
+            Some(line) if (baseline - line.baseline).abs() <= BASELINE_TOLERANCE * span.rect.height().min(line.height()) => {
+                line.rect = line.rect.union_rect(span.rect);
+                line.spans.push(span);
+            }
+            _ => lines.push(TextLine {
+                spans: vec![span],
+                rect: span.rect,
+                baseline,
+            }),
+        }
+    }
+    for line in lines.iter_mut() {
+        line.spans.sort_by(|a, b| a.rect.min_x().total_cmp(&b.rect.min_x()));
+    }
+    lines
+}
+
+// Whether a line `gap` below the one before it starts a paragraph, which it does when it is
+// more than `paragraph_gap` times `line_spacing`, the gap between the lines before it.
+// Otherwise it is the line spacing from then on.
+pub fn paragraph_break(gap: f32, line_spacing: &mut Option<f32>, paragraph_gap: f32) -> bool {
+    let paragraph = line_spacing.is_some_and(|spacing| gap > paragraph_gap * spacing);
+    if !paragraph {
+        *line_spacing = Some(gap);
+    }
+    paragraph
+}
+
+// The lines in paragraphs, broken where the text has a blank line (see paragraph_break),
+// at a first-line indent and at the top of the next column.
+pub fn group_paragraphs(lines: Vec<TextLine<'_>>, paragraph_gap: f32) -> Vec<Paragraph<'_>> {
+    let mut line_spacing = None;
+    let mut paragraphs: Vec<Paragraph> = vec![];
+    for line in lines {
+        let starts_paragraph = match paragraphs.last().and_then(|p| p.lines.last()) {
+            None => true,
+            Some(prev) => {
+                let em = prev.height().max(line.height());
+                let gap = line.baseline - prev.baseline;
+                // a line above the one before it is the top of the next column
+                let spaced = gap < 0. || paragraph_break(gap, &mut line_spacing, paragraph_gap);
+                // a first-line indent
+                let indented = line.rect.min_x() > prev.rect.min_x() + em;
+                spaced || indented
+            }
+        };
+        match paragraphs.last_mut() {
+            Some(paragraph) if !starts_paragraph => {
+                paragraph.rect = paragraph.rect.union_rect(line.rect);
+                paragraph.lines.push(line);
+            }
+            _ => paragraphs.push(Paragraph {
+                rect: line.rect,
+                lines: vec![line],
+            }),
+        }
+    }
+    paragraphs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdf_render::{testpdf, FontEntry};
+    use crate::tests::span;
+    use crate::PageTextOptions;
+
+    fn joined(text: &str, next: &str) -> Option<String> {
+        let mut text = text.to_string();
+        join_hyphenated(&mut text, next).then_some(text)
+    }
+
+    #[test]
+    fn hyphenated_words() {
+        assert_eq!(joined("infor-", "mation"), Some("infor".into()));
+        assert_eq!(joined("infor\u{AD}", " mation"), Some("infor".into()));
+        // a name or the start of a sentence in the next line, a dash, or no hyphen at all
+        assert_eq!(joined("Jean-", "Luc"), None);
+        assert_eq!(joined("pages 3 -", "five"), None);
+        assert_eq!(joined("1-", "two"), None);
+        assert_eq!(joined("word", "next"), None);
+        assert_eq!(joined("-", "next"), None);
+        // a compound broken at its own hyphen looks the same, and loses it too
+        assert_eq!(joined("well-", "known"), Some("well".into()));
+    }
+    #[test]
+    fn two_lines() {
+        let spans = vec![
+            span("Hello", 10., 20., 4.),
+            span("world", 21., 20., 4.),
+            span("next", 10., 26., 4.),
+        ];
+        let lines = group_lines(&spans, &PageTextOptions::default());
+        let text: Vec<String> = lines.iter().map(|line| line.text(WORD_GAP)).collect();
+        assert_eq!(text, ["Hello world", "next"]);
+        // one above the other
+        assert_eq!((lines[0].rect.min_y(), lines[0].rect.max_y()), (16., 20.));
+        assert_eq!((lines[1].rect.min_y(), lines[1].rect.max_y()), (22., 26.));
+        assert_eq!(lines[0].rect.max_x(), 31.);
+    }
+    #[test]
+    fn lines_of_columns() {
+        // the lines of the text, a column after the other, not rows across them
+        let spans = vec![
+            span("left", 10., 20., 4.), span("right", 100., 20., 4.),
+            span("column", 10., 26., 4.), span("column", 100., 26., 4.),
+        ];
+        let text: Vec<String> = group_lines(&spans, &PageTextOptions::default()).iter().map(|line| line.text(WORD_GAP)).collect();
+        assert_eq!(text, ["left", "column", "right", "column"]);
+        let rows: Vec<String> = group_rows(&spans).iter().map(|line| line.text(WORD_GAP)).collect();
+        assert_eq!(rows, ["left right", "column column"]);
+    }
+    #[test]
+    fn two_paragraphs() {
+        // two lines each, with a blank line between them
+        let spans: Vec<_> = ["The first", "paragraph.", "The second", "one."].iter()
+            .zip([20., 25., 35., 40.])
+            .map(|(text, baseline)| span(text, 10., baseline, 4.))
+            .collect();
+        let options = PageTextOptions::default();
+        let paragraphs = group_paragraphs(group_lines(&spans, &options), options.paragraph_gap);
+        let text: Vec<String> = paragraphs.iter().map(|p| p.text(false, WORD_GAP)).collect();
+        assert_eq!(text, ["The first paragraph.", "The second one."]);
+        assert_eq!((paragraphs[0].rect.min_y(), paragraphs[0].rect.max_y()), (16., 25.));
+        assert_eq!((paragraphs[1].rect.min_y(), paragraphs[1].rect.max_y()), (31., 40.));
+        // twice the line spacing is within a paragraph for a gap of 2.5
+        let paragraphs = group_paragraphs(group_lines(&spans, &options), 2.5);
+        assert_eq!(paragraphs.len(), 1);
+    }
+    #[test]
+    fn collapsed_whitespace() {
+        assert_eq!(collapse_whitespace("a  b\t\tc \t d"), "a b c d");
+        // indents and line breaks stay, the ends of lines are trimmed
+        assert_eq!(collapse_whitespace("  a   b  \n\tc \n\n d"), "  a b\n\tc\n\n d");
+        assert_eq!(collapse_whitespace(" \t "), "");
+    }
+    #[test]
+    fn merged_spans() {
+        // a space is a quarter of the 4mm size, 1mm
+        let spans = || vec![
+            span("Hel", 10., 20., 4.),
+            // a little kerning
+            span("lo", 15.75, 20., 4.),
+            span("world", 20.5, 20., 4.),
+            // the next line, and another size
+            span("next", 10., 25., 4.),
+            span("small", 18.25, 25., 2.),
+        ];
+        let text = |spans: &[TextSpan]| spans.iter().map(|span| span.text.clone()).collect::<Vec<_>>();
+        assert_eq!(text(&merge_spans(spans(), 0.5, WORD_GAP)), ["Hello", "world", "next", "small"]);
+
+        let merged = merge_spans(spans(), 1., WORD_GAP);
+        assert_eq!(text(&merged), ["Hello world", "next", "small"]);
+        let first = &merged[0];
+        assert_eq!(first.width, 20.5);
+        assert_eq!(first.rect.max_x(), 30.5);
+        // the space is a char of its own, filling the gap
+        let chars: Vec<_> = first.chars.iter().map(|c| (c.offset, c.pos, c.width)).collect();
+        assert_eq!(chars[3 .. 7], [(3, 5.75, 2.), (4, 7.75, 2.), (5, 9.75, 0.75), (6, 10.5, 2.)]);
+    }
+    #[cfg(feature = "unicode-segmentation")]
+    #[test]
+    fn clusters() {
+        // combining marks, a virama and a joiner continue the char before them
+        assert!(continues_cluster("cafe", "\u{301}s"));
+        assert!(continues_cluster("\u{915}", "\u{94D}\u{937}"));
+        assert!(continues_cluster("a", "\u{200D}"));
+        assert!(!continues_cluster("a", "b"));
+        assert!(!continues_cluster("", "\u{301}"));
+        assert!(!continues_cluster("a", ""));
+    }
+    #[test]
+    fn spaced_ideographs() {
+        assert_eq!(space_ideographs("東京2024年の地図map", false), "東京 2024 年の地図 map");
+        // punctuation and spaces that are there already
+        assert_eq!(space_ideographs("中文，abc 中文", false), "中文，abc 中文");
+        assert_eq!(space_ideographs("中文abc", true), "中 文 abc");
+        assert_eq!(space_ideographs("한국어", true), "한 국 어");
+        assert_eq!(space_ideographs("plain text", true), "plain text");
+    }
+    // a font of its own, a Type3 font needs no font program
+    fn font() -> Arc<FontEntry> {
+        let (file, font) = testpdf::font(&[
+            b"<< /Type /Font /Subtype /Type3 /FontBBox [0 0 1000 1000] /FontMatrix [0.001 0 0 0.001 0 0] /CharProcs << >> /FirstChar 32 /LastChar 32 /Widths [250] >>",
+        ]);
+        Arc::new(FontEntry::type3(font, &file).unwrap())
+    }
+
+    #[test]
+    fn word_spaces() {
+        let (mut wor, mut l) = (span("wor", 10., 20., 4.), span("l", 16.25, 20., 4.));
+        // a symbol font with almost no space
+        l.space_advance = 0.1;
+        let line = |spans: [&TextSpan; 2]| TextLine { spans: spans.to_vec(), rect: spans[0].rect.union_rect(spans[1].rect), baseline: 20. }.text(WORD_GAP);
+        assert_eq!(word_space(&wor, &l), 0.1);
+        assert_eq!(line([&wor, &l]), "wor l");
+
+        // another font measures against the wider of the two spaces
+        wor.font = Some(font());
+        l.font = Some(font());
+        assert_eq!(word_space(&wor, &l), 1.);
+        assert_eq!(line([&wor, &l]), "worl");
+    }
+}
@@ -1,98 +1,1206 @@
+mod annotations;
+#[cfg(feature = "bidi")]
+mod bidi;
+mod columns;
+mod filter;
+mod layout;
+mod lines;
+mod metadata;
+mod objects;
+mod outline;
+mod output;
+mod portfolio;
+mod stats;
+mod table;
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::ops::Range;
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{ArgAction, Parser, ValueEnum};
+use pathfinder_geometry::{rect::RectF, transform2d::Transform2F, vector::Vector2F};
+use pdf::error::PdfError;
 use pdf::file::File;
-use pdf_render::tracer::{DrawItem, TraceCache, Tracer};
-use pdf_render::{render_page, TextSpan};
+use pdf::object::{PageRc, Resolve};
+#[cfg(feature = "salvage")]
+use pdf_render::tracer::Tracer;
+use pdf_render::tracer::{page_fonts, page_text_spans, PageTextSpans, TextOptions, TraceCache};
+use pdf_render::{FontOverrides, MissingFont, StandardCache, TextSpan, TjSpacing, Undecoded};
+#[cfg(feature = "salvage")]
+use pdf_render::salvage_page;
+
+use annotations::{page_annotations, page_links};
+use columns::split_columns;
+use filter::filter_spans;
+use lines::{continues_cluster, join_hyphenated, paragraph_break, word_space, WORD_GAP};
+use outline::Destinations;
+use output::{PageSpans, PageText};
+use portfolio::{embedded_pdfs, is_portfolio};
+use stats::Stats;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// The PDF to read, or - to read it from stdin
     input: PathBuf,
 
+    /// Password of an encrypted PDF
+    #[arg(long)]
+    password: Option<String>,
+
+    /// File to write the text to, or a directory to write a file per page to, like page-0000.txt for the first
     #[arg(short, long)]
     output: Option<PathBuf>,
 
+    /// Only read this page, counted from 0
     #[arg(short, long)]
     page: Option<usize>,
+
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Encoding of the output, with a byte order mark for the tools that need one
+    #[arg(long, value_enum, default_value_t = OutputEncoding::Utf8)]
+    encoding: OutputEncoding,
+
+    /// Only keep text that starts within x0,y0,x1,y1, in user space (points, y pointing up)
+    #[arg(long, value_parser = parse_rect, allow_hyphen_values = true)]
+    crop: Option<RectF>,
+
+    /// Print the title, author, subject, keywords and creation date before the text
+    #[arg(long)]
+    metadata: bool,
+
+    /// Print the bookmarks of the document before the text
+    #[arg(long)]
+    toc: bool,
+
+    /// List the fonts of every page and how their text is decoded, instead of the text
+    #[arg(long)]
+    list_fonts: bool,
+
+    /// Add the comments of a page after its text
+    #[arg(long)]
+    annotations: bool,
+
+    /// Reflow the text into paragraphs separated by blank lines
+    #[arg(long)]
+    paragraphs: bool,
+
+    /// Keep the physical layout by placing the text on a grid of characters
+    #[arg(long, conflicts_with = "paragraphs")]
+    layout: bool,
+
+    /// Width of a character on the layout grid, in mm
+    #[arg(long, default_value_t = 1.8, requires = "layout")]
+    cell_width: f32,
+
+    /// Height of a line on the layout grid, in mm
+    #[arg(long, default_value_t = 4.2, requires = "layout")]
+    cell_height: f32,
+
+    /// Render this many pages at once
+    #[cfg(feature = "parallel")]
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+
+    /// Don't print a header to stderr for every page
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Print a summary of the extraction to stderr
+    #[arg(long)]
+    stats: bool,
+
+    /// Only report which pages have text, to stderr, without writing it
+    #[arg(long)]
+    check: bool,
+
+    #[command(flatten)]
+    text: PageTextOptions,
+
+    /// What to emit for characters that can't be decoded
+    #[arg(long, value_enum, default_value_t = UndecodedArg::Drop)]
+    undecoded: UndecodedArg,
+
+    /// Emit U+FFFD for characters that can't be decoded, like --undecoded replacement
+    #[arg(long, conflicts_with = "undecoded")]
+    lossy: bool,
+
+    /// How the gaps within a TJ array become spaces
+    #[arg(long, value_enum, default_value_t = TjSpacingArg::Relative)]
+    tj_spacing: TjSpacingArg,
+
+    /// Decode a code of a font as the given text, like Times-Roman:0x41=Ä (repeatable)
+    #[arg(long = "map-code", value_parser = parse_override)]
+    overrides: Vec<(String, u16, String)>,
+
+    /// What to emit for text drawn in a font that can't be loaded
+    #[arg(long, value_enum, default_value_t = MissingFontArg::Drop)]
+    missing_font: MissingFontArg,
+
+    /// Skip what fails to render and report it, instead of giving up on the page
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Scan the raw content stream for text when a page fails to parse
+    #[cfg(feature = "salvage")]
+    #[arg(long)]
+    salvage: bool,
+}
+
+fn parse_rect(s: &str) -> Result<RectF, String> {
+    let v: Vec<f32> = s.split(',')
+        .map(|v| v.trim().parse().map_err(|_| format!("invalid number {:?}", v)))
+        .collect::<Result<_, _>>()?;
+    match v[..] {
+        [x0, y0, x1, y1] => Ok(RectF::from_points(
+            Vector2F::new(x0.min(x1), y0.min(y1)),
+            Vector2F::new(x0.max(x1), y0.max(y1)),
+        )),
+        _ => Err("expected x0,y0,x1,y1".into()),
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    /// Plain text in reading order
+    Text,
+    /// The spans of each page with their rectangles
+    Json,
+    /// A JSON object for every line, with its text and rectangle
+    Lines,
+    /// HTML with the bounding boxes of pages, lines and words, as OCR engines produce it
+    Hocr,
+    /// The text as a table, a row for every line and a cell between gutters
+    Csv,
+    /// Markdown, with the lines set larger than the body text as headings
+    Md,
+    /// Every span in reading order with its position, font and the gaps that placed it
+    #[cfg(feature = "debug-geometry")]
+    Debug,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OutputEncoding {
+    /// UTF-8 without a byte order mark
+    Utf8,
+    /// UTF-8 starting with a byte order mark
+    Utf8Bom,
+    /// UTF-16, little endian, starting with a byte order mark
+    Utf16le,
+}
+impl OutputEncoding {
+    fn bom(self) -> &'static [u8] {
+        match self {
+            OutputEncoding::Utf8 => b"",
+            OutputEncoding::Utf8Bom => b"\xEF\xBB\xBF",
+            OutputEncoding::Utf16le => b"\xFF\xFE",
+        }
+    }
+    fn encode(self, text: &str) -> Cow<'_, [u8]> {
+        match self {
+            OutputEncoding::Utf8 | OutputEncoding::Utf8Bom => Cow::Borrowed(text.as_bytes()),
+            OutputEncoding::Utf16le => Cow::Owned(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum MissingFontArg {
+    /// Leave the text out
+    Drop,
+    /// Emit U+FFFD for every byte
+    Placeholder,
+    /// Decode the bytes as StandardEncoding
+    Guess,
+}
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum UndecodedArg {
+    /// Leave them out
+    Drop,
+    /// Emit U+FFFD
+    Replacement,
+    /// Emit the code in hex, like <1F>
+    Hex,
+    /// Emit the private use character U+F0000 + code
+    PrivateUse,
+}
+impl From<UndecodedArg> for Undecoded {
+    fn from(arg: UndecodedArg) -> Self {
+        match arg {
+            UndecodedArg::Drop => Undecoded::Drop,
+            UndecodedArg::Replacement => Undecoded::Replacement,
+            UndecodedArg::Hex => Undecoded::Hex,
+            UndecodedArg::PrivateUse => Undecoded::PrivateUse,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum TjSpacingArg {
+    /// A space for every gap wider than 0.1 units of text space
+    Fixed,
+    /// Judged by the width of a space in the font: a space between words and a tab for a jump to another column
+    Relative,
+}
+impl From<TjSpacingArg> for TjSpacing {
+    fn from(arg: TjSpacingArg) -> Self {
+        match arg {
+            TjSpacingArg::Fixed => TjSpacing::Fixed,
+            TjSpacingArg::Relative => TjSpacing::Relative,
+        }
+    }
+}
+
+impl From<MissingFontArg> for MissingFont {
+    fn from(arg: MissingFontArg) -> Self {
+        match arg {
+            MissingFontArg::Drop => MissingFont::Drop,
+            MissingFontArg::Placeholder => MissingFont::Placeholder,
+            MissingFontArg::Guess => MissingFont::Guess,
+        }
+    }
+}
+
+// thresholds for turning the gaps between spans into spaces and line breaks, relative to
+// the size of the text so that they hold for small print and titles alike
+#[derive(clap::Args, Clone, Debug)]
+struct PageTextOptions {
+    /// Positions are compared in steps of the median text height divided by this
+    #[arg(long, default_value_t = 16.)]
+    position_steps: f32,
+
+    /// Start a new line when the text jumps back left by more than this many text heights
+    #[arg(long, default_value_t = 3.)]
+    line_jump: f32,
+
+    /// Read the text in this many columns instead of detecting them
+    #[arg(long)]
+    columns: Option<usize>,
+
+    /// Separate spans by a space when their gap exceeds this fraction of a space
+    #[arg(long, default_value_t = WORD_GAP)]
+    word_gap: f32,
+
+    /// Start a new line when the baseline moves by more than this fraction of the text height
+    #[arg(long, default_value_t = 0.5)]
+    line_gap: f32,
+
+    /// Separate paragraphs by a blank line where lines are further apart than this many times the line spacing
+    #[arg(long, default_value_t = 1.5)]
+    paragraph_gap: f32,
+
+    /// Wrap text raised or lowered by more than this fraction of its font size in ^{…} or _{…}
+    #[arg(long)]
+    script_rise: Option<f32>,
+
+    /// Leave out invisible text, like the OCR layer of a scan or hidden watermarks.
+    /// Pages with text mode 7 (invisible and clipping) fail to parse, --salvage still gets their text
+    #[arg(long)]
+    skip_invisible: bool,
+
+    /// Leave out text a tagged PDF marks as an artifact, like running headers and page numbers
+    #[arg(long)]
+    skip_artifacts: bool,
+
+    /// Merge spans that follow each other on a baseline with gaps up to this fraction of a space, before reading them
+    #[arg(long)]
+    merge_gap: Option<f32>,
+
+    /// Drop spans where less than this fraction of the characters decoded to real text, not to control, private use or replacement characters
+    #[arg(long)]
+    min_confidence: Option<f32>,
+
+    /// Drop text drawn a second time over itself, as done to fake bold
+    #[arg(long)]
+    dedup_overlapping: bool,
+
+    /// Join words hyphenated across line breaks
+    #[arg(long)]
+    dehyphenate: bool,
+
+    /// Collapse runs of spaces and tabs within lines into one space and trim them at line ends
+    #[arg(long)]
+    collapse_whitespace: bool,
+
+    /// Spaces to put into Chinese, Japanese and Korean text, which is written without them
+    #[arg(long, value_enum, default_value_t = CjkSpace::None)]
+    cjk_space: CjkSpace,
+
+    /// Keep text outside of the crop box of the page, like printer's marks or hidden notes
+    #[arg(long = "keep-off-page", action = ArgAction::SetFalse)]
+    clip_to_cropbox: bool,
+
+    /// Keep soft hyphens (U+00AD) in the text
+    #[arg(long = "keep-soft-hyphens", action = ArgAction::SetFalse)]
+    strip_soft_hyphens: bool,
+
+    /// Unicode normalization to apply to the text
+    #[cfg(feature = "unicode-normalization")]
+    #[arg(long, value_enum)]
+    normalize: Option<NormalizationForm>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum CjkSpace {
+    /// Only where the gaps on the page are wide enough
+    None,
+    /// Also where ideographs meet words of other scripts
+    Segment,
+    /// Also between every two ideographs, to index each on its own
+    PerChar,
+}
+
+#[cfg(feature = "unicode-normalization")]
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum NormalizationForm {
+    /// Canonical composition ("e" and a combining acute become "é")
+    Nfc,
+    /// Canonical decomposition
+    Nfd,
+}
+// the defaults of the command line options, so they are given in one place
+impl Default for PageTextOptions {
+    fn default() -> Self {
+        let command = <PageTextOptions as clap::Args>::augment_args(clap::Command::new("pdf2text"));
+        let matches = command.get_matches_from(["pdf2text"]);
+        <PageTextOptions as clap::FromArgMatches>::from_arg_matches(&matches).expect("text options without defaults")
+    }
+}
+
+// direction of the writing in whole degrees, so spans of one rotated block end up together.
+// vertical text runs a quarter turn from its baseline, its columns are read like lines
+// of text turned that way, from right to left.
+fn rotation(span: &TextSpan) -> i32 {
+    let t = span.transform;
+    let angle = t.m21().atan2(t.m11()).to_degrees().round() as i32;
+    if span.vertical() {
+        (angle + 270).rem_euclid(360) - 180
+    } else {
+        angle
+    }
+}
+
+fn items2text(items: &[TextSpan], options: &PageTextOptions) -> String {
+    items2text_with_offsets(items, options).0
+}
+
+// Like items2text, but also returns the byte range of the text that each item ended up
+// at, so that a position in the text can be traced back to its span.
+fn items2text_with_offsets(items: &[TextSpan], options: &PageTextOptions) -> PageText {
+    let (text, placements) = place_spans(items, options);
+    let index: HashMap<*const TextSpan, usize> = items.iter().enumerate().map(|(i, item)| (item as *const _, i)).collect();
+    let mut offsets = vec![None; items.len()];
+    for placement in placements {
+        offsets[index[&(placement.span as *const _)]] = Some(placement.range);
+    }
+    (text, offsets)
+}
+
+// where reading_order put a span, and the gaps to the span before that decided how
+#[cfg_attr(not(feature = "debug-geometry"), allow(dead_code))]
+struct Placement<'a> {
+    span: &'a TextSpan,
+    range: Range<usize>,
+    x_diff: f32,
+    y_diff: f32,
+}
+
+// the page text and the placement of each span, in reading order
+fn place_spans<'a>(items: &'a [TextSpan], options: &PageTextOptions) -> (String, Vec<Placement<'a>>) {
+    // rotated spans (e.g. side labels or table headers) are read in their own upright
+    // order instead of being interleaved with the body text.
+    let mut groups: BTreeMap<i32, Vec<(RectF, &TextSpan)>> = BTreeMap::new();
+    for item in items {
+        let angle = rotation(item);
+        let upright = Transform2F::from_rotation(-(angle as f32).to_radians()) * item.rect;
+        groups.entry(angle).or_default().push((upright, item));
+    }
+
+    let mut placements = vec![];
+    let mut res = String::new();
+
+    let body = groups.remove(&0);
+    let blocks = body.into_iter()
+        .chain(groups.into_values())
+        .flat_map(|spans| {
+            let height = median_height(&spans);
+            split_columns(spans, height, options.columns)
+        });
+    for mut spans in blocks {
+        let mut block_placements = vec![];
+        let text = reading_order(&mut spans, options, &mut block_placements);
+        if text.is_empty() {
+            continue;
+        }
+        if !res.is_empty() {
+            res.push('\n');
+        }
+        for mut placement in block_placements {
+            placement.range = placement.range.start + res.len() .. placement.range.end + res.len();
+            placements.push(placement);
+        }
+        res += &text;
+    }
+    (res, placements)
+}
+
+// One line per span in reading order: where its text went, its origin in mm, font and
+// size, and the gaps to the span before it in units of the median text height.
+#[cfg(feature = "debug-geometry")]
+fn geometry(items: &[TextSpan], options: &PageTextOptions) -> String {
+    use std::fmt::Write;
+
+    let (text, placements) = place_spans(items, options);
+    let mut out = String::new();
+    for p in placements {
+        let origin = p.span.transform.translation();
+        let font = p.span.font.as_ref().map_or("-", |font| font.name.as_str());
+        writeln!(out, "{:?} {:?} at ({:.1}, {:.1}) {} {:.1}mm dx {:.2} dy {:.2}",
+            p.range, &text[p.range.clone()], origin.x(), origin.y(), font, p.span.device_font_size(), p.x_diff, p.y_diff
+        ).unwrap();
+    }
+    out
+}
+
+// the median height of the spans, which is about the size of the body text
+fn median_height(items: &[(RectF, &TextSpan)]) -> f32 {
+    let mut heights: Vec<f32> = items.iter().map(|(rect, _)| rect.height()).filter(|&h| h > 0.).collect();
+    if heights.is_empty() {
+        return 1.;
+    }
+    heights.sort_by(f32::total_cmp);
+    heights[heights.len() / 2]
 }
 
-fn items2text(items: &mut Vec<&TextSpan>) -> String {
-    let factor = 5.;
+fn reading_order<'a>(items: &mut Vec<(RectF, &'a TextSpan)>, options: &PageTextOptions, placements: &mut Vec<Placement<'a>>) -> String {
+    let height = median_height(items);
+    let factor = options.position_steps / height;
+    let line_jump = options.line_jump * height;
 
     let norm_pos = |x: f32| (x * factor) as i32;
 
     let mut res = String::new();
-    items.sort_by_key(|x| (x.rect.0[1] as i32, norm_pos(x.rect.0[0])));
+    // by baseline, so spans of different sizes on one line stay together. Baselines closer
+    // than a line break make a row, which is read from left to right.
+    items.sort_by(|(a, _), (b, _)| a.max_y().total_cmp(&b.max_y()));
+    let mut rows: Vec<Vec<(RectF, &'a TextSpan)>> = vec![];
+    let mut prev_rect: Option<RectF> = None;
+    for &(rect, item) in items.iter() {
+        let same_row = prev_rect.is_some_and(|prev| rect.max_y() - prev.max_y() <= options.line_gap * rect.height().min(prev.height()));
+        match rows.last_mut() {
+            Some(row) if same_row => row.push((rect, item)),
+            _ => rows.push(vec![(rect, item)]),
+        }
+        prev_rect = Some(rect);
+    }
+    for row in rows.iter_mut() {
+        row.sort_by_key(|(rect, _)| norm_pos(rect.min_x()));
+    }
+    *items = rows.concat();
 
     if items.is_empty() {
         return res;
     }
 
-    let mut prev_y = 0.;
+    let mut prev_baseline = 0.;
+    let mut prev_height = 0.;
     let mut prev_x = 0.;
-    for item in items.iter() {
-        let x_diff = (norm_pos(item.rect.0[0]) - norm_pos(prev_x)) as f32 / factor;
-        if !res.is_empty() {
-            if x_diff < -10. || norm_pos(item.rect.0[1]) >= norm_pos(prev_y) {
+    let mut prev_item: Option<&TextSpan> = None;
+    // the distance between the baselines of the last lines within a paragraph
+    let mut line_spacing: Option<f32> = None;
+    for (rect, item) in items.iter() {
+        let x_diff = (norm_pos(rect.min_x()) - norm_pos(prev_x)) as f32 / factor;
+        // a superscript or a larger word moves the baseline less than a line does
+        let y_diff = (rect.max_y() - prev_baseline).abs();
+        let new_line = y_diff > options.line_gap * rect.height().min(prev_height);
+        let mut joined = false;
+        if !res.is_empty() && (x_diff < -line_jump || new_line) {
+            let down = rect.max_y() > prev_baseline;
+            let paragraph = down && paragraph_break(y_diff, &mut line_spacing, options.paragraph_gap);
+            if paragraph {
+                res += "\n\n";
+            } else if options.dehyphenate && join_hyphenated(&mut res, &item.text) {
+                joined = true;
+            } else {
                 res += "\n";
             }
         }
 
-        if !res.is_empty() && !res.ends_with("\n") {
-            if x_diff > 0.1 {
+        if !joined && !res.is_empty() && !res.ends_with("\n") {
+            let space = prev_item.map_or(item.space_width(), |prev| word_space(prev, item));
+            if x_diff > options.word_gap * space && !continues_cluster(&res, &item.text) {
                 res += " ";
             }
         }
 
-        res += &item.text;
-        // res += "\t";
-        // res += &format!(" ({:?}) ", item.rect);
+        let start = res.len();
+        match options.script_rise {
+            Some(f) if item.rise > f * item.font_size => res += &format!("^{{{}}}", item.text),
+            Some(f) if item.rise < -f * item.font_size => res += &format!("_{{{}}}", item.text),
+            _ => res += &item.text,
+        }
+        placements.push(Placement { span: item, range: start .. res.len(), x_diff, y_diff });
 
-        prev_x = item.rect.0[2];
-        prev_y = item.rect.0[3];
+        prev_x = rect.max_x();
+        prev_item = Some(item);
+        prev_baseline = rect.max_y();
+        prev_height = rect.height();
     }
 
     res
 }
 
-fn main() {
-    let args = Args::parse();
+// FONT:CODE=TEXT, the code in decimal or hex with 0x
+fn parse_override(s: &str) -> Result<(String, u16, String), String> {
+    let (code, text) = s.split_once('=').ok_or("expected FONT:CODE=TEXT")?;
+    let (font, code) = code.rsplit_once(':').ok_or("expected FONT:CODE=TEXT")?;
+    let code = match code.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => code.parse(),
+    };
+    let code = code.map_err(|_| format!("invalid code in {:?}", s))?;
+    Ok((font.into(), code, text.into()))
+}
+
+// the parsed standard fonts are shared by all threads and all documents of a portfolio
+fn trace_cache(args: &Args, std: &StandardCache) -> TraceCache {
+    let mut overrides = FontOverrides::new();
+    for (font, code, text) in args.overrides.iter() {
+        overrides.add(font, *code, text);
+    }
+    TraceCache::with_standard_fonts(std.clone()).with_overrides(overrides)
+}
+
+fn text_options(args: &Args) -> TextOptions {
+    TextOptions {
+        missing_font: args.missing_font.into(),
+        undecoded: match args.lossy {
+            true => Undecoded::Replacement,
+            false => args.undecoded.into(),
+        },
+        tj_spacing: args.tj_spacing.into(),
+        keep_going: args.keep_going,
+    }
+}
 
-    let file = File::open(args.input).expect("failed to read PDF");
-    let mut cache = TraceCache::new();
-    let mut backend = Tracer::new(&mut cache);
+// the view box of the page, the transform from user space and its text
+type PageRender = (RectF, Transform2F, Vec<TextSpan>);
 
+fn page_spans(args: &Args, resolve: &impl Resolve, page: &PageRc, cache: &TraceCache) -> Result<PageRender, PdfError> {
+    let options = text_options(args);
+    let result = page_text_spans(resolve, page, cache, &options);
+
+    #[cfg(feature = "salvage")]
+    let result = match result {
+        Err(e) if args.salvage => {
+            eprintln!("failed to analyze page ({:?}), salvaging its text", e);
+            let mut backend = Tracer::with_options(cache, &options);
+            salvage_page(&mut backend, resolve, page, Default::default()).map(|transform| PageTextSpans {
+                view_box: backend.view_box(),
+                transform,
+                spans: backend.text_spans(),
+                errors: vec![],
+            })
+        }
+        result => result,
+    };
+    let PageTextSpans { view_box, transform, spans, errors } = result?;
+    for e in errors {
+        eprintln!("skipped part of the page: {:?}", e);
+    }
+
+    // the crop box in device space
+    let crop = args.crop.map(|rect| transform * rect);
+    // what a viewer shows of the page, the crop box falls back to the media box
+    let visible = match args.text.clip_to_cropbox {
+        true => page.crop_box().ok().map(|b| transform * RectF::from_points(
+            Vector2F::new(b.left.min(b.right), b.bottom.min(b.top)),
+            Vector2F::new(b.left.max(b.right), b.bottom.max(b.top)),
+        )),
+        false => None,
+    };
+    let spans = filter_spans(spans, &args.text, crop, visible);
+    Ok((view_box, transform, spans))
+}
+
+// the spans of every page, or of every document in a portfolio, passed to `emit` one page
+// at a time as they are rendered
+fn document_pages(args: &Args, file: &File<Vec<u8>>, document: Option<&str>, std: &StandardCache, stats: &mut Stats, emit: &mut dyn FnMut(PageSpans)) {
+    if let Some(catalog) = objects::catalog(file).filter(is_portfolio) {
+        for embedded in embedded_pdfs(file, &catalog) {
+            match File::from_data(embedded.data) {
+                Ok(child) => document_pages(args, &child, Some(&embedded.name), std, stats, emit),
+                Err(e) => eprintln!("failed to read embedded file {}: {:?}", embedded.name, e),
+            }
+        }
+        return;
+    }
+
+    // fonts are cached by reference, which is only unique within one file
+    let cache = trace_cache(args, std);
+
+    // annotations and links are read from the page dictionaries
+    let dests = match args.annotations || matches!(args.format, Format::Json) {
+        true => objects::catalog(file).map(|catalog| Destinations::new(file, &catalog)),
+        false => None,
+    };
+
+    // a page that can't be read is left out, the others still are
+    let mut add_page = |page_nr, result: Result<PageRender, PdfError>| {
+        let (view_box, transform, spans) = match result {
+            Ok(page) => page,
+            Err(e) => {
+                eprintln!("failed to analyze page {}: {:?}", page_nr, e);
+                stats.add_failed_page(page_nr);
+                return;
+            }
+        };
+        stats.add_page(page_nr, &spans);
+        let page_ref = dests.as_ref().and_then(|dests| Some((dests, *dests.pages.get(page_nr)?)));
+        let annotations = match page_ref {
+            Some((_, page)) if args.annotations => page_annotations(file, page, transform),
+            _ => vec![],
+        };
+        let links = match page_ref {
+            Some((dests, page)) if matches!(args.format, Format::Json) => page_links(file, page, dests, transform),
+            _ => vec![],
+        };
+        emit(PageSpans { document: document.map(String::from), page_nr, view_box, spans, annotations, links });
+    };
     if let Some(page_i) = args.page {
-        let page = file
-            .pages()
-            .nth(page_i)
-            .expect(&format!("invalid page {}", page_i))
-            .expect(&format!("invalid page {}", page_i));
-        render_page(&mut backend, &file, &page, Default::default()).expect("failed to analyze PDF");
+        let page = match file.pages().nth(page_i) {
+            Some(page) => page,
+            None => {
+                eprintln!("there is no page {}", page_i);
+                std::process::exit(1);
+            }
+        };
+        add_page(page_i, page.and_then(|page| page_spans(args, file, &page, &cache)));
     } else {
+        #[cfg(feature = "parallel")]
+        if args.jobs > 1 {
+            // the pages come in order, once all of them are rendered
+            let all: Vec<Result<PageRc, PdfError>> = file.pages().collect();
+            for (page_nr, spans) in parallel_page_spans(args, file, all, std).into_iter().enumerate() {
+                if !args.quiet {
+                    eprintln!("=== PAGE {} ===\n", page_nr);
+                }
+                add_page(page_nr, spans);
+            }
+            return;
+        }
         for (page_nr, page) in file.pages().enumerate() {
-            let page = page.expect(&format!("invalid page {}", page_nr));
-            eprintln!("=== PAGE {} ===\n", page_nr);
-            render_page(&mut backend, &file, &page, Default::default())
-                .expect("failed to analyze PDF");
+            if !args.quiet {
+                eprintln!("=== PAGE {} ===\n", page_nr);
+            }
+            add_page(page_nr, page.and_then(|page| page_spans(args, file, &page, &cache)));
         }
     }
+}
 
-    let items = backend.finish();
-    let mut items: Vec<&TextSpan> = items
-        .iter()
-        .filter_map(|item| match item {
-            DrawItem::Text(text) => Some(text),
-            _ => None,
-        })
-        .collect();
+// pages in the order given, rendered by `args.jobs` threads with a font cache each
+#[cfg(feature = "parallel")]
+fn parallel_page_spans(args: &Args, file: &File<Vec<u8>>, pages: Vec<Result<PageRc, PdfError>>, std: &StandardCache) -> Vec<Result<PageRender, PdfError>> {
+    use rayon::prelude::*;
 
-    let res = items2text(&mut items);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()
+        .expect("failed to start threads");
+    pool.install(|| {
+        pages.into_par_iter()
+            .map_init(|| trace_cache(args, std), |cache, page| page.and_then(|page| page_spans(args, file, &page, cache)))
+            .collect()
+    })
+}
 
-    if let Some(out_path) = args.output {
-        std::fs::write(out_path, res).expect("failed to write to file");
+fn open_file(args: &Args) -> File<Vec<u8>> {
+    // without a password, the empty user password is tried
+    let password = args.password.as_deref().unwrap_or("").as_bytes();
+    let result = if args.input.as_os_str() != "-" {
+        File::open_password(&args.input, password)
     } else {
-        println!("{}", res);
+        let mut data = vec![];
+        std::io::stdin().read_to_end(&mut data).expect("failed to read stdin");
+        if data.is_empty() {
+            eprintln!("no PDF on stdin");
+            std::process::exit(1);
+        }
+        File::from_data_password(data, password)
+    };
+    match result {
+        Ok(file) => file,
+        Err(PdfError::InvalidPassword) if args.password.is_some() => {
+            eprintln!("wrong password");
+            std::process::exit(1);
+        }
+        Err(PdfError::InvalidPassword) => {
+            eprintln!("the PDF is encrypted, give its password with --password");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("failed to read PDF: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let file = open_file(&args);
+    let mut stats = Stats::default();
+
+    let std = StandardCache::new(PathBuf::from("."));
+    if args.list_fonts {
+        list_fonts(&args, &file, &std);
+        return;
+    }
+    if args.check {
+        let mut pages = vec![];
+        document_pages(&args, &file, None, &std, &mut stats, &mut |page| pages.push(page));
+        stats::check(&pages);
+    } else {
+        let mut output = output::Output::new(&args, &file);
+        document_pages(&args, &file, None, &std, &mut stats, &mut |page| output.page(page));
+        output.finish();
+    }
+    if args.stats {
+        stats.print();
+    }
+    // what could be read is written, but the text is incomplete
+    if stats.failed() {
+        std::process::exit(1);
+    }
+}
+
+// a line for every font of a page: page, resource name, font name, type, embedded, decoding
+fn list_fonts(args: &Args, file: &File<Vec<u8>>, std: &StandardCache) {
+    let cache = trace_cache(args, std);
+    for (page_nr, page) in file.pages().enumerate() {
+        if args.page.is_some_and(|page_i| page_i != page_nr) {
+            continue;
+        }
+        let fonts = match page.and_then(|page| page_fonts(file, &page, &cache)) {
+            Ok(fonts) => fonts,
+            Err(e) => {
+                eprintln!("failed to read the fonts of page {}: {:?}", page_nr, e);
+                continue;
+            }
+        };
+        for font in fonts {
+            let decoding = match font.decode_source {
+                Some(source) => format!("{:?}", source),
+                None => "missing".into(),
+            };
+            println!("{}\t{}\t{}\t{:?}\t{}\t{}",
+                page_nr,
+                font.resource,
+                font.name.as_deref().unwrap_or("-"),
+                font.subtype,
+                if font.embedded { "embedded" } else { "not embedded" },
+                decoding,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use pdf::content::TextMode;
+    use pdf_render::{testpdf, Fill, FontEntry, TextChar};
+    use std::sync::Arc;
+
+    // a span of upright text starting at x on the baseline, in mm with y pointing down.
+    // every char is half an em wide and a space a quarter.
+    pub(crate) fn span(text: &str, x: f32, baseline: f32, size: f32) -> TextSpan {
+        let advance = 0.5 * size;
+        let width = advance * text.chars().count() as f32;
+        let rect = RectF::from_points(Vector2F::new(x, baseline - size), Vector2F::new(x + width, baseline));
+        TextSpan {
+            rect,
+            font_rect: rect,
+            width,
+            bbox: Some(rect),
+            font_size: size,
+            space_advance: 0.25 * size,
+            rise: 0.,
+            font: None,
+            text: text.into(),
+            chars: text.char_indices().enumerate()
+                .map(|(i, (offset, _))| TextChar { offset, pos: i as f32 * advance, width: advance })
+                .collect(),
+            color: Fill::Solid(0., 0., 0.),
+            alpha: 1.,
+            mode: TextMode::Fill,
+            artifact: false,
+            transform: Transform2F::from_translation(Vector2F::new(x, baseline)),
+        }
+    }
+
+    // spans that the fixed and the relative thresholds read differently
+    fn page() -> Vec<TextSpan> {
+        vec![
+            span("Hello", 10., 20., 4.),
+            span("world", 20.3, 20., 4.),
+            span("next", 10., 25., 4.),
+            span("2", 18.2, 23., 2.),
+        ]
+    }
+
+    #[test]
+    fn lossy_replaces_undecoded_characters() {
+        let undecoded = |args: &[&str]| text_options(&Args::parse_from(["pdf2text"].iter().chain(args).chain(&["in.pdf"]))).undecoded;
+        assert_eq!(undecoded(&[]), Undecoded::Drop);
+        assert_eq!(undecoded(&["--lossy"]), Undecoded::Replacement);
+        assert_eq!(undecoded(&["--undecoded", "hex"]), Undecoded::Hex);
+        // rather than one of the two winning
+        assert!(Args::try_parse_from(["pdf2text", "--lossy", "--undecoded", "hex", "in.pdf"]).is_err());
+    }
+
+    #[test]
+    fn default_thresholds() {
+        // a gap of 0.3mm is less than half a space, and the raised 2 is off the baseline by
+        // more than half its height, which takes it to a line of its own
+        assert_eq!(items2text(&page(), &PageTextOptions::default()), "Helloworld\n2\nnext");
+    }
+
+    #[test]
+    fn position_steps() {
+        // in 4mm text positions are compared in steps of 0.25mm, a gap of 0.2mm is none
+        let spans = || vec![
+            span("Hello", 10., 20., 4.),
+            span("world", 20.2, 20., 4.),
+        ];
+        // a tenth of a space, 0.1mm
+        let options = PageTextOptions {
+            word_gap: 0.1,
+            ..PageTextOptions::default()
+        };
+        assert_eq!(items2text(&spans(), &options), "Helloworld");
+        let options = PageTextOptions {
+            position_steps: 100.,
+            ..options
+        };
+        assert_eq!(items2text(&spans(), &options), "Hello world");
+    }
+
+    #[test]
+    fn spans_on_one_baseline() {
+        // the small word starts below the top of the title, but it is on the same line
+        let spans = vec![
+            span("see", 10., 20., 2.),
+            span("Title", 20., 20., 8.),
+        ];
+        assert_eq!(items2text(&spans, &PageTextOptions::default()), "see Title");
+    }
+
+    #[test]
+    fn baselines_around_a_row_step() {
+        // a hair apart, on either side of a multiple of half the 4mm text height
+        let spans = vec![
+            span("world", 21., 19.99, 4.),
+            span("Hello", 10., 20.01, 4.),
+        ];
+        assert_eq!(items2text(&spans, &PageTextOptions::default()), "Hello world");
+    }
+
+    #[test]
+    fn word_gaps() {
+        // "a" and "b" of a size with a gap between them
+        let text = |size: f32, gap: f32, options: &PageTextOptions| {
+            let spans = vec![
+                span("a", 10., 20., size),
+                span("b", 10. + 0.5 * size + gap, 20., size),
+            ];
+            items2text(&spans, options)
+        };
+        let options = PageTextOptions::default();
+        // half a space makes a word gap in any size
+        assert_eq!(text(1., 0.2, &options), "a b");
+        assert_eq!(text(4., 0.2, &options), "ab");
+        assert_eq!(text(4., 0.8, &options), "a b");
+        assert_eq!(text(8., 0.8, &options), "ab");
+        assert_eq!(text(8., 1.6, &options), "a b");
+
+        let options = PageTextOptions {
+            word_gap: 0.2,
+            ..PageTextOptions::default()
+        };
+        assert_eq!(text(8., 0.8, &options), "a b");
+    }
+    #[test]
+    fn paragraphs() {
+        // three lines with a leading of 5mm, twice that, and three more
+        let spans: Vec<_> = ["one", "two", "three", "four", "five", "six"].iter()
+            .zip([20., 25., 30., 40., 45., 50.])
+            .map(|(text, baseline)| span(text, 10., baseline, 4.))
+            .collect();
+        assert_eq!(items2text(&spans, &PageTextOptions::default()), "one\ntwo\nthree\n\nfour\nfive\nsix");
+    }
+
+    #[test]
+    fn default_text_options() {
+        // those of the command line, where the flags that turn something off leave it on
+        let options = PageTextOptions::default();
+        assert!(options.clip_to_cropbox && options.strip_soft_hyphens && !options.dehyphenate);
+        assert_eq!((options.position_steps, options.word_gap, options.paragraph_gap), (16., WORD_GAP, 1.5));
+    }
+
+    // `span` turned by `angle` degrees around its origin, -90 reads upwards
+    fn rotated(text: &str, x: f32, baseline: f32, size: f32, angle: f32) -> TextSpan {
+        let transform = Transform2F::from_translation(Vector2F::new(x, baseline)) * Transform2F::from_rotation(angle.to_radians());
+        let upright = span(text, 0., 0., size);
+        let rect = transform * upright.rect;
+        TextSpan { rect, font_rect: rect, bbox: Some(rect), transform, ..upright }
+    }
+
+    #[test]
+    fn rotated_side_label() {
+        // a label up the left margin, next to the lines of the body
+        let spans = vec![
+            span("Body", 20., 20., 4.),
+            rotated("Side", 10., 40., 4., -90.),
+            span("one", 31., 20., 4.),
+            rotated("label", 10., 31., 4., -90.),
+            span("Body", 20., 26., 4.),
+            span("two", 31., 26., 4.),
+        ];
+        assert_eq!(items2text(&spans, &PageTextOptions::default()), "Body one\nBody two\nSide label");
+    }
+
+    // a column of text in a vertical font, from its top down, every char an em high
+    fn column(text: &str, x: f32, top: f32, size: f32) -> TextSpan {
+        let (file, font) = testpdf::font(&[
+            b"<< /Type /Font /Subtype /Type3 /FontBBox [0 0 1000 1000] /FontMatrix [0.001 0 0 0.001 0 0] /CharProcs << >> >>",
+        ]);
+        let mut font = FontEntry::type3(font, &file).unwrap();
+        font.vertical = true;
+        let width = size * text.chars().count() as f32;
+        let rect = RectF::from_points(Vector2F::new(x - 0.5 * size, top), Vector2F::new(x + 0.5 * size, top + width));
+        TextSpan {
+            rect,
+            font_rect: rect,
+            width,
+            bbox: Some(rect),
+            font: Some(Arc::new(font)),
+            chars: text.char_indices().enumerate()
+                .map(|(i, (offset, _))| TextChar { offset, pos: i as f32 * size, width: size })
+                .collect(),
+            transform: Transform2F::from_translation(Vector2F::new(x, top)),
+            ..span(text, x, top, size)
+        }
+    }
+
+    #[test]
+    fn vertical_columns() {
+        // two columns of Japanese, read from right to left
+        let spans = vec![
+            column("\u{306E}\u{6587}\u{7AE0}", 40., 20., 4.),
+            column("\u{7E26}\u{66F8}\u{304D}", 46., 20., 4.),
+        ];
+        assert!(spans.iter().all(|span| span.vertical()));
+        assert_eq!(items2text(&spans, &PageTextOptions::default()), "\u{7E26}\u{66F8}\u{304D}\n\u{306E}\u{6587}\u{7AE0}");
+    }
+
+    #[test]
+    fn footnote_marker() {
+        // a raised digit after a word, up by 0.4 of its size; as in the renderer only the
+        // font rect moves with the rise, the span stays on the baseline
+        let mut marker = span("1", 18., 20., 4.);
+        marker.rise = 1.6;
+        marker.font_rect = marker.font_rect + Vector2F::new(0., -1.6);
+        let spans = vec![span("note", 10., 20., 4.), marker];
+        assert_eq!(items2text(&spans, &PageTextOptions::default()), "note1");
+        let options = PageTextOptions {
+            script_rise: Some(0.3),
+            ..PageTextOptions::default()
+        };
+        assert_eq!(items2text(&spans, &options), "note^{1}");
+        let options = PageTextOptions {
+            script_rise: Some(0.5),
+            ..PageTextOptions::default()
+        };
+        assert_eq!(items2text(&spans, &options), "note1");
+    }
+
+    #[test]
+    fn empty_user_password() {
+        // RC4 encrypted with 40 bit keys, the content stream is "BT /F1 10 Tf 10 10 Td (Hi) Tj ET"
+        let content = testpdf::stream(b"\xB6\xE9\x12\xB5\x35\x39\xF4\x17\x16\x3B\x63\x24\x28\x02\x12\x0B\x7E\x77\xF2\x66\x89\xD7\xA2\x0B\x51\x33\xFC\xEB\xF5\xC9\x22\xE6");
+        let data = testpdf::pdf_with_trailer(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>",
+            b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> >> /Contents 6 0 R >>",
+            b"<< /Type /Font /Subtype /Type3 /FontBBox [0 0 1000 1000] /FontMatrix [0.001 0 0 0.001 0 0] /CharProcs << >> /FirstChar 72 /LastChar 72 /Widths [500] /Encoding << /Differences [72 /H 105 /i] >> >>",
+            b"<< >>",
+            &content,
+            b"<< /Filter /Standard /V 1 /R 2 /P -4 /O <2055C756C72E1AD702608E8196ACAD447AD32D17CFF583235F6DD15FED7DAB67> /U <B6271BB74F4FD4BF931172DCDE8682912EDC27B84AD0DC7CB83DC19FB91734D5> >>",
+        ], "/Encrypt 7 0 R /ID [<000102030405060708090A0B0C0D0E0F> <000102030405060708090A0B0C0D0E0F>]");
+        let path = std::env::temp_dir().join(format!("pdf2text-encrypted-{}.pdf", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        // without --password
+        let file = open_file(&Args::parse_from(["pdf2text", path.to_str().unwrap()]));
+        std::fs::remove_file(&path).unwrap();
+        let page = file.get_page(0).unwrap();
+        let spans = page_text_spans(&file, &page, &TraceCache::new(), &TextOptions::default()).unwrap().spans;
+        assert_eq!(spans.iter().map(|span| span.text.as_str()).collect::<Vec<_>>(), ["Hi"]);
+    }
+
+    #[test]
+    fn crop_rects() {
+        let rect = |x0, y0, x1, y1| RectF::from_points(Vector2F::new(x0, y0), Vector2F::new(x1, y1));
+        assert_eq!(parse_rect("10,20,300,400"), Ok(rect(10., 20., 300., 400.)));
+        // any two corners, and negative numbers
+        assert_eq!(parse_rect("300, 400, -10, 20"), Ok(rect(-10., 20., 300., 400.)));
+        assert!(parse_rect("10,20,300").is_err());
+        assert!(parse_rect("10,20,300,x").is_err());
+        let args = Args::parse_from(["pdf2text", "--crop", "-10,20,300,400", "in.pdf"]);
+        assert_eq!(args.crop, Some(rect(-10., 20., 300., 400.)));
+    }
+
+    #[test]
+    fn span_offsets() {
+        // drawn in another order than they are read
+        let spans = vec![
+            span("second line", 10., 30., 4.),
+            span("Search", 10., 20., 4.),
+            span("hit", 24., 20., 4.),
+        ];
+        let (text, offsets) = items2text_with_offsets(&spans, &PageTextOptions::default());
+        assert_eq!(text, "Search hit\nsecond line");
+        // the hit leads back to the rect of its span
+        let start = text.find("hit").unwrap();
+        let i = offsets.iter().position(|range| range.as_ref().is_some_and(|r| r.contains(&start))).unwrap();
+        assert_eq!(spans[i].rect, RectF::from_points(Vector2F::new(24., 16.), Vector2F::new(30., 20.)));
+        assert_eq!(offsets, [Some(11..22), Some(0..6), Some(7..10)]);
+    }
+
+    #[test]
+    fn code_overrides() {
+        let parsed = |s| parse_override(s).unwrap();
+        assert_eq!(parsed("Broken:66=B"), ("Broken".into(), 66, "B".into()));
+        assert_eq!(parsed("ABCDEF+Symbol:0x2022=\u{2022}"), ("ABCDEF+Symbol".into(), 0x2022, "\u{2022}".into()));
+        // the text may hold anything, the font name colons
+        assert_eq!(parsed("a:b:0x41=x=y"), ("a:b".into(), 0x41, "x=y".into()));
+        assert!(parse_override("Broken=B").is_err());
+        assert!(parse_override("Broken:0xG=B").is_err());
+        assert!(parse_override("Broken:70000=B").is_err());
+    }
+
+    #[cfg(feature = "debug-geometry")]
+    #[test]
+    fn debug_geometry() {
+        // why the 2 got a line of its own, and world no space
+        let expected = concat!(
+            "0..5 \"Hello\" at (10.0, 20.0) - 4.0mm dx 10.00 dy 20.00\n",
+            "5..10 \"world\" at (20.3, 20.0) - 4.0mm dx 0.25 dy 0.00\n",
+            "11..12 \"2\" at (18.2, 23.0) - 2.0mm dx -12.25 dy 3.00\n",
+            "13..17 \"next\" at (10.0, 25.0) - 4.0mm dx -9.00 dy 2.00\n",
+        );
+        assert_eq!(geometry(&page(), &PageTextOptions::default()), expected);
+    }
+
+    #[test]
+    fn note_outside_the_crop_box() {
+        // the page inherits its boxes, the note is on the media box but off the crop box
+        let [type3, to_unicode] = testpdf::abc_font(4);
+        let data = testpdf::pdf_with_objects(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>",
+            b"<< /Type /Pages /Kids [3 0 R] /Count 1 /MediaBox [0 0 200 200] /CropBox [0 0 100 100] >>",
+            b"<< /Type /Page /Parent 2 0 R /Resources << /Font << /F1 4 0 R >> >> /Contents 6 0 R >>",
+            &type3,
+            &to_unicode,
+            &testpdf::stream(b"BT /F1 10 Tf 10 10 Td (AB) Tj 140 140 Td (C) Tj ET"),
+        ]);
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let text = |args: &[&str]| {
+            let args = Args::parse_from(["pdf2text"].iter().chain(args).chain(&["in.pdf"]));
+            let (_, _, spans) = page_spans(&args, &file, &page, &TraceCache::new()).unwrap();
+            spans.into_iter().map(|span| span.text).collect::<Vec<_>>()
+        };
+        assert_eq!(text(&[]), ["AB"]);
+        assert_eq!(text(&["--keep-off-page"]), ["AB", "C"]);
+    }
+
+    #[test]
+    fn horizontal_scaling() {
+        // letters 10 units apart, twice the width of their glyphs, until Tz 200 widens them
+        let text = |scaling: u32| {
+            let [type3, to_unicode] = testpdf::abc_font(4);
+            let content = format!("BT /F1 10 Tf {} Tz 10 10 Td (A) Tj 10 0 Td (B) Tj 10 0 Td (C) Tj ET", scaling);
+            let data = testpdf::pdf_with_page(
+                b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> >> /Contents 6 0 R >>",
+                &[&type3, &to_unicode, &testpdf::stream(content.as_bytes())],
+            );
+            let file = File::from_data(data).unwrap();
+            let page = file.get_page(0).unwrap();
+            let spans = page_text_spans(&file, &page, &TraceCache::new(), &TextOptions::default()).unwrap().spans;
+            items2text(&spans, &PageTextOptions::default())
+        };
+        assert_eq!(text(100), "A B C");
+        assert_eq!(text(200), "ABC");
+    }
+
+    #[test]
+    fn rotated_page() {
+        // shown turned a quarter clockwise, so the body is drawn running up the page and the
+        // label drawn across it is what reads sideways
+        let [type3, to_unicode] = testpdf::abc_font(4);
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 100] /Rotate 90 /Resources << /Font << /F1 4 0 R >> >> /Contents 6 0 R >>",
+            &[
+                &type3,
+                &to_unicode,
+                &testpdf::stream(b"BT /F1 10 Tf 150 50 Td (C) Tj 0 1 -1 0 20 10 Tm (AB) Tj 0 1 -1 0 40 10 Tm (BA) Tj ET"),
+            ],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let args = Args::parse_from(["pdf2text", "in.pdf"]);
+        let (_, _, spans) = page_spans(&args, &file, &page, &TraceCache::new()).unwrap();
+        assert_eq!(items2text(&spans, &args.text), "AB\nBA\nC");
+    }
+
+    #[test]
+    fn corrupt_page() {
+        // the content stream of the first page claims to be compressed, but isn't
+        let [type3, to_unicode] = testpdf::abc_font(5);
+        let data = testpdf::pdf_with_objects(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>",
+            b"<< /Type /Pages /Kids [3 0 R 4 0 R] /Count 2 /MediaBox [0 0 200 200] /Resources << /Font << /F1 5 0 R >> >> >>",
+            b"<< /Type /Page /Parent 2 0 R /Contents 7 0 R >>",
+            b"<< /Type /Page /Parent 2 0 R /Contents 8 0 R >>",
+            &type3,
+            &to_unicode,
+            &testpdf::stream_with("/Filter /FlateDecode", b"BT /F1 10 Tf 10 10 Td (AB) Tj ET"),
+            &testpdf::stream(b"BT /F1 10 Tf 10 10 Td (C) Tj ET"),
+        ]);
+        let file = File::from_data(data).unwrap();
+        let args = Args::parse_from(["pdf2text", "--quiet", "in.pdf"]);
+        let std = StandardCache::new(PathBuf::from("."));
+        let mut stats = Stats::default();
+        let mut pages = vec![];
+        document_pages(&args, &file, None, &std, &mut stats, &mut |page| pages.push(page));
+        // the page is left out and counted, the next one still read
+        let pages: Vec<_> = pages.iter().map(|page| (page.page_nr, page.spans[0].text.as_str())).collect();
+        assert_eq!(pages, [(1, "C")]);
+        assert!(stats.failed());
+    }
+
+    #[test]
+    fn output_encodings() {
+        assert_eq!(OutputEncoding::Utf8.bom(), b"");
+        assert_eq!(OutputEncoding::Utf8Bom.bom(), b"\xEF\xBB\xBF");
+        assert_eq!(OutputEncoding::Utf16le.bom(), b"\xFF\xFE");
+        assert_eq!(&*OutputEncoding::Utf8Bom.encode("é"), b"\xC3\xA9");
+        // a char outside the basic plane takes a surrogate pair
+        assert_eq!(&*OutputEncoding::Utf16le.encode("aé\u{1F600}"), b"a\0\xE9\0\x3D\xD8\x00\xDE");
     }
 }
@@ -0,0 +1,130 @@
+use std::fmt::Write;
+
+use pdf::file::File;
+use pdf::primitive::Primitive;
+use pdf_render::text_string;
+
+use crate::objects::resolved;
+
+// the entries of the document information dictionary that say what the document is
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    pub creation_date: Option<String>,
+}
+
+pub fn document_metadata(file: &File<Vec<u8>>) -> Metadata {
+    let info = file.trailer.info_dict.as_ref();
+    let entry = |key: &str| match info.and_then(|info| info.get(key)).and_then(|p| resolved(file, p)) {
+        Some(Primitive::String(s)) => Some(text_string(s.as_bytes())).filter(|s| !s.trim().is_empty()),
+        _ => None,
+    };
+    Metadata {
+        title: entry("Title"),
+        author: entry("Author"),
+        subject: entry("Subject"),
+        keywords: entry("Keywords"),
+        creation_date: entry("CreationDate").map(|date| date_time(&date).unwrap_or(date)),
+    }
+}
+
+impl Metadata {
+    // one "Key: value" line for every entry the document has
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        let entries = [
+            ("Title", &self.title),
+            ("Author", &self.author),
+            ("Subject", &self.subject),
+            ("Keywords", &self.keywords),
+            ("Created", &self.creation_date),
+        ];
+        for (key, value) in entries {
+            if let Some(value) = value {
+                writeln!(out, "{}: {}", key, value).unwrap();
+            }
+        }
+        out
+    }
+}
+
+// A date like D:20230401123000+02'00' as 2023-04-01T12:30:00+02:00. Everything after the
+// year is optional, the missing parts are left out.
+fn date_time(date: &str) -> Option<String> {
+    let s = date.strip_prefix("D:").unwrap_or(date);
+    let digits = s.bytes().take_while(u8::is_ascii_digit).count();
+    if digits < 4 || digits % 2 != 0 || digits > 14 {
+        return None;
+    }
+    let (d, zone) = s.split_at(digits);
+    let mut out = d[.. 4].to_string();
+    for (i, sep) in ["-", "-", "T", ":", ":"].iter().enumerate() {
+        match d.get(4 + 2 * i .. 6 + 2 * i) {
+            Some(part) => {
+                out += sep;
+                out += part;
+            }
+            None => break,
+        }
+    }
+    // the time zone needs a time to go with
+    if digits < 10 {
+        return Some(out);
+    }
+    if digits == 10 {
+        out += ":00";
+    }
+    match zone.as_bytes().first() {
+        Some(b'Z') => out.push('Z'),
+        Some(&sign @ (b'+' | b'-')) => {
+            let zone: String = zone[1 ..].chars().filter(char::is_ascii_digit).collect();
+            match zone.len() {
+                2 => write!(out, "{}{}:00", sign as char, zone).unwrap(),
+                4 => write!(out, "{}{}:{}", sign as char, &zone[.. 2], &zone[2 ..]).unwrap(),
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdf_render::testpdf;
+
+    #[test]
+    fn dates() {
+        assert_eq!(date_time("D:20230401123000+02'00'").as_deref(), Some("2023-04-01T12:30:00+02:00"));
+        assert_eq!(date_time("D:20230401123000Z").as_deref(), Some("2023-04-01T12:30:00Z"));
+        assert_eq!(date_time("20230401123000-0530").as_deref(), Some("2023-04-01T12:30:00-05:30"));
+        assert_eq!(date_time("D:20230401123000+02").as_deref(), Some("2023-04-01T12:30:00+02:00"));
+        // the parts that are given
+        assert_eq!(date_time("D:2023").as_deref(), Some("2023"));
+        assert_eq!(date_time("D:202304").as_deref(), Some("2023-04"));
+        assert_eq!(date_time("D:2023040112").as_deref(), Some("2023-04-01T12:00"));
+        // a zone without a time
+        assert_eq!(date_time("D:20230401Z").as_deref(), Some("2023-04-01"));
+        assert_eq!(date_time("D:202").as_deref(), None);
+        assert_eq!(date_time("D:20230").as_deref(), None);
+        assert_eq!(date_time("yesterday").as_deref(), None);
+    }
+
+    #[test]
+    fn utf16_title() {
+        // Résumé in UTF-16BE with its byte order mark, and an empty subject
+        let data = testpdf::pdf_with_trailer(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>",
+            b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] >>",
+            b"<< /Title <FEFF005200E900730075006D00E9> /Author (Jane Doe) /Subject ( ) /CreationDate (D:20230401123000Z) >>",
+        ], "/Info 4 0 R");
+        let file = File::from_data(data).unwrap();
+        let metadata = document_metadata(&file);
+        assert_eq!(metadata.title.as_deref(), Some("R\u{e9}sum\u{e9}"));
+        assert_eq!(metadata.text(), "Title: R\u{e9}sum\u{e9}\nAuthor: Jane Doe\nCreated: 2023-04-01T12:30:00Z\n");
+    }
+}
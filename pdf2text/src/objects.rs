@@ -0,0 +1,110 @@
+use pdf::file::File;
+use pdf::object::{PlainRef, Resolve};
+use pdf::primitive::{Dictionary, Primitive};
+
+// follow a reference, if `p` is one
+pub fn resolved(resolve: &impl Resolve, p: &Primitive) -> Option<Primitive> {
+    match *p {
+        Primitive::Reference(r) => resolve.resolve(r).ok(),
+        ref p => Some(p.clone()),
+    }
+}
+
+pub fn dict(resolve: &impl Resolve, p: &Primitive) -> Option<Dictionary> {
+    match resolved(resolve, p)? {
+        Primitive::Dictionary(dict) => Some(dict),
+        _ => None,
+    }
+}
+
+pub fn entry(resolve: &impl Resolve, dict: &Dictionary, key: &str) -> Option<Dictionary> {
+    self::dict(resolve, dict.get(key)?)
+}
+
+pub fn number(resolve: &impl Resolve, p: &Primitive) -> Option<f32> {
+    match resolved(resolve, p)? {
+        Primitive::Integer(n) => Some(n as f32),
+        Primitive::Number(n) => Some(n),
+        _ => None,
+    }
+}
+
+// a /Rect as [x0, y0, x1, y1] in user space
+pub fn rect(resolve: &impl Resolve, p: &Primitive) -> Option<[f32; 4]> {
+    match resolved(resolve, p)? {
+        Primitive::Array(a) if a.len() == 4 => {
+            let mut rect = [0.; 4];
+            for (v, p) in rect.iter_mut().zip(a.iter()) {
+                *v = number(resolve, p)?;
+            }
+            Some(rect)
+        }
+        _ => None,
+    }
+}
+
+pub fn catalog(file: &File<Vec<u8>>) -> Option<Dictionary> {
+    let root = file.trailer.root.get_ref().get_inner();
+    match file.resolve(root) {
+        Ok(Primitive::Dictionary(dict)) => Some(dict),
+        _ => None,
+    }
+}
+
+// name, page and outline trees are shallow in practice, this only guards against cycles
+pub const MAX_DEPTH: usize = 32;
+
+// call `f` with every key and value of a name tree
+pub fn walk_name_tree(resolve: &impl Resolve, node: &Dictionary, f: &mut impl FnMut(&Primitive, &Primitive)) {
+    walk_name_tree_at(resolve, node, f, 0)
+}
+
+fn walk_name_tree_at(resolve: &impl Resolve, node: &Dictionary, f: &mut impl FnMut(&Primitive, &Primitive), depth: usize) {
+    if depth > MAX_DEPTH {
+        eprintln!("name tree is too deep");
+        return;
+    }
+    if let Some(Primitive::Array(names)) = node.get("Names").and_then(|p| resolved(resolve, p)) {
+        // alternating keys and values
+        for pair in names.chunks_exact(2) {
+            f(&pair[0], &pair[1]);
+        }
+    }
+    if let Some(Primitive::Array(kids)) = node.get("Kids").and_then(|p| resolved(resolve, p)) {
+        for kid in kids.iter() {
+            if let Some(kid) = dict(resolve, kid) {
+                walk_name_tree_at(resolve, &kid, f, depth + 1);
+            }
+        }
+    }
+}
+
+// the references of the pages in order, to turn destinations into page numbers
+pub fn page_refs(resolve: &impl Resolve, catalog: &Dictionary) -> Vec<PlainRef> {
+    let mut pages = vec![];
+    if let Some(&Primitive::Reference(root)) = catalog.get("Pages") {
+        walk_pages(resolve, root, &mut pages, 0);
+    }
+    pages
+}
+
+fn walk_pages(resolve: &impl Resolve, node: PlainRef, pages: &mut Vec<PlainRef>, depth: usize) {
+    if depth > MAX_DEPTH {
+        eprintln!("page tree is too deep");
+        return;
+    }
+    let node_dict = match resolve.resolve(node) {
+        Ok(Primitive::Dictionary(dict)) => dict,
+        _ => return,
+    };
+    match node_dict.get("Kids").and_then(|p| resolved(resolve, p)) {
+        Some(Primitive::Array(kids)) => {
+            for kid in kids.iter() {
+                if let Primitive::Reference(kid) = *kid {
+                    walk_pages(resolve, kid, pages, depth + 1);
+                }
+            }
+        }
+        _ => pages.push(node),
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use pdf::file::File;
+use pdf::object::{PlainRef, Resolve};
+use pdf::primitive::{Dictionary, Primitive};
+use pdf_render::text_string;
+
+use crate::objects::{catalog, dict, entry, page_refs, resolved, walk_name_tree, MAX_DEPTH};
+
+// a bookmark, with the index of the page it points to
+pub struct OutlineItem {
+    pub title: String,
+    pub page: Option<usize>,
+    pub children: Vec<OutlineItem>,
+}
+
+pub struct Destinations {
+    pub pages: Vec<PlainRef>,
+    // named destinations, from the /Dests name tree and the older /Dests dictionary
+    named: HashMap<Vec<u8>, Primitive>,
+}
+
+impl Destinations {
+    pub fn new(resolve: &impl Resolve, catalog: &Dictionary) -> Destinations {
+        let mut named = HashMap::new();
+        if let Some(tree) = entry(resolve, catalog, "Names").and_then(|names| entry(resolve, &names, "Dests")) {
+            walk_name_tree(resolve, &tree, &mut |key, dest| {
+                if let Primitive::String(ref s) = *key {
+                    named.insert(s.as_bytes().to_vec(), dest.clone());
+                }
+            });
+        }
+        if let Some(dests) = entry(resolve, catalog, "Dests") {
+            for (key, dest) in dests.iter() {
+                named.insert(key.as_str().as_bytes().to_vec(), dest.clone());
+            }
+        }
+        Destinations { pages: page_refs(resolve, catalog), named }
+    }
+
+    // the page of an explicit destination, a named one, or a dictionary with /D
+    pub fn page(&self, resolve: &impl Resolve, dest: &Primitive, depth: usize) -> Option<usize> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        match resolved(resolve, dest)? {
+            Primitive::Array(dest) => match *dest.first()? {
+                Primitive::Reference(page) => self.pages.iter().position(|&r| r == page),
+                // destinations in other documents give the page number
+                Primitive::Integer(n) => usize::try_from(n).ok(),
+                _ => None,
+            },
+            Primitive::Name(ref name) => self.page(resolve, self.named.get(name.as_str().as_bytes())?, depth + 1),
+            Primitive::String(ref s) => self.page(resolve, self.named.get(s.as_bytes())?, depth + 1),
+            Primitive::Dictionary(ref dict) => self.page(resolve, dict.get("D")?, depth + 1),
+            _ => None,
+        }
+    }
+
+    // where a bookmark or link goes: its /Dest, or the /D of a GoTo action
+    pub fn target(&self, resolve: &impl Resolve, item: &Dictionary) -> Option<usize> {
+        if let Some(dest) = item.get("Dest") {
+            return self.page(resolve, dest, 0);
+        }
+        let action = entry(resolve, item, "A")?;
+        match action.get("S") {
+            Some(Primitive::Name(s)) if s.as_str() == "GoTo" => self.page(resolve, action.get("D")?, 0),
+            _ => None,
+        }
+    }
+}
+
+pub fn document_outline(file: &File<Vec<u8>>) -> Vec<OutlineItem> {
+    let catalog = match catalog(file) {
+        Some(catalog) => catalog,
+        None => return vec![],
+    };
+    let dests = Destinations::new(file, &catalog);
+    match entry(file, &catalog, "Outlines").and_then(|outlines| outlines.get("First").cloned()) {
+        Some(first) => items(file, &dests, &first, 0),
+        None => vec![],
+    }
+}
+
+// the siblings starting at `first`, with their children
+fn items(resolve: &impl Resolve, dests: &Destinations, first: &Primitive, depth: usize) -> Vec<OutlineItem> {
+    let mut items = vec![];
+    if depth > MAX_DEPTH {
+        eprintln!("outline is too deep");
+        return items;
+    }
+    let mut seen = vec![];
+    let mut next = Some(first.clone());
+    while let Some(node) = next.take() {
+        // a /Next that loops back would never end
+        if let Primitive::Reference(r) = node {
+            if seen.contains(&r) {
+                break;
+            }
+            seen.push(r);
+        }
+        let item = match dict(resolve, &node) {
+            Some(item) => item,
+            None => break,
+        };
+        let title = match item.get("Title").and_then(|p| resolved(resolve, p)) {
+            Some(Primitive::String(s)) => text_string(s.as_bytes()),
+            _ => String::new(),
+        };
+        let children = match item.get("First") {
+            Some(first) => self::items(resolve, dests, first, depth + 1),
+            None => vec![],
+        };
+        items.push(OutlineItem { title, page: dests.target(resolve, &item), children });
+        next = item.get("Next").cloned();
+    }
+    items
+}
+
+// the outline as an indented table of contents
+pub fn toc(items: &[OutlineItem]) -> String {
+    let mut out = String::new();
+    write_items(&mut out, items, 0);
+    out
+}
+
+fn write_items(out: &mut String, items: &[OutlineItem], level: usize) {
+    for item in items {
+        match item.page {
+            Some(page) => writeln!(out, "{:indent$}{} ({})", "", item.title, page, indent = 2 * level).unwrap(),
+            None => writeln!(out, "{:indent$}{}", "", item.title, indent = 2 * level).unwrap(),
+        }
+        write_items(out, &item.children, level + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdf_render::testpdf;
+
+    #[test]
+    fn nested_bookmarks() {
+        let data = testpdf::pdf_with_objects(&[
+            b"<< /Type /Catalog /Pages 2 0 R /Outlines 4 0 R /Dests << /intro [3 0 R /Fit] >> >>",
+            b"<< /Type /Pages /Kids [3 0 R 5 0 R] /Count 2 >>",
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] >>",
+            b"<< /Type /Outlines /First 6 0 R /Last 7 0 R /Count 4 >>",
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] >>",
+            // a named destination, and a GoTo action with children
+            b"<< /Title (Introduction) /Parent 4 0 R /Next 7 0 R /Dest /intro >>",
+            b"<< /Title (Chapter) /Parent 4 0 R /Prev 6 0 R /First 8 0 R /Last 9 0 R /A << /S /GoTo /D [5 0 R /Fit] >> >>",
+            // an explicit destination with a UTF-16 title, and none at all
+            b"<< /Title <FEFF00DC0062006500720073006900630068007400> /Parent 7 0 R /Next 9 0 R /Dest [5 0 R /XYZ 0 200 0] >>",
+            b"<< /Title (Notes) /Parent 7 0 R /Prev 8 0 R >>",
+        ]);
+        let file = File::from_data(data).unwrap();
+        let outline = document_outline(&file);
+        assert_eq!(toc(&outline), "Introduction (0)\nChapter (1)\n  \u{DC}bersicht (1)\n  Notes\n");
+    }
+}
@@ -0,0 +1,872 @@
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::io::{self, Write as _};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use pathfinder_geometry::{rect::RectF, vector::Vector2F};
+use pdf::file::File;
+use pdf_render::{Fill, TextSpan};
+use serde::Serialize;
+#[cfg(feature = "unicode-normalization")]
+use unicode_normalization::UnicodeNormalization;
+
+use crate::annotations::{Annotation, Link, LinkTarget};
+#[cfg(feature = "bidi")]
+use crate::bidi;
+#[cfg(feature = "debug-geometry")]
+use crate::geometry;
+#[cfg(feature = "unicode-normalization")]
+use crate::NormalizationForm;
+use crate::layout::layout_text;
+use crate::lines::{collapse_whitespace, group_lines, group_paragraphs, space_ideographs, Paragraph, TextLine};
+use crate::metadata::document_metadata;
+use crate::outline::{self, document_outline};
+use crate::table::table;
+use crate::{items2text, items2text_with_offsets, Args, CjkSpace, Format, OutputEncoding, PageTextOptions};
+
+// the spans of one page, and the document of a portfolio they come from
+pub struct PageSpans {
+    pub document: Option<String>,
+    pub page_nr: usize,
+    // in mm, like the spans
+    pub view_box: RectF,
+    pub spans: Vec<TextSpan>,
+    pub annotations: Vec<Annotation>,
+    pub links: Vec<Link>,
+}
+
+#[derive(Serialize)]
+struct JsonPage<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    document: Option<&'a str>,
+    page: usize,
+    // the spans in reading order
+    text: &'a str,
+    spans: Vec<JsonSpan<'a>>,
+    // the spans grouped by baseline, from the top
+    lines: Vec<JsonPageLine>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    annotations: Vec<JsonAnnotation<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    links: Vec<JsonLink<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonPageLine {
+    // indices into the spans of the page, left to right
+    spans: Vec<usize>,
+    // the union of the rects of its spans, in mm
+    rect: [f32; 4],
+    // y of the baseline in mm
+    baseline: f32,
+}
+
+#[derive(Serialize)]
+struct JsonLink<'a> {
+    // either the address or the index of the page it goes to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uri: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<usize>,
+    // in mm, like the spans
+    rect: [f32; 4],
+}
+
+#[derive(Serialize)]
+struct JsonAnnotation<'a> {
+    subtype: &'a str,
+    rect: [f32; 4],
+    contents: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonSpan<'a> {
+    text: &'a str,
+    // min x, min y, max x, max y in mm, y pointing down
+    rect: [f32; 4],
+    // red, green and blue from 0 to 1, absent for text filled with a pattern
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<[f32; 3]>,
+    // without the subset prefix
+    #[serde(skip_serializing_if = "Option::is_none")]
+    font: Option<&'a str>,
+    // height of an em in mm
+    size: f32,
+    // the text runs down the page
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    vertical: bool,
+    // the range of characters of the page text that it makes up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset: Option<[usize; 2]>,
+}
+
+// subsets are named like ABCDEF+Times-Roman
+fn font_name(name: &str) -> &str {
+    match name.split_once('+') {
+        Some((tag, rest)) if tag.len() == 6 && tag.bytes().all(|b| b.is_ascii_uppercase()) => rest,
+        _ => name,
+    }
+}
+
+// the page text, and the byte range of each of its spans
+pub type PageText = (String, Vec<Option<Range<usize>>>);
+
+// the char index of every byte offset, offsets within a char count as after it
+fn char_indices(text: &str) -> Vec<usize> {
+    let mut indices = Vec::with_capacity(text.len() + 1);
+    let mut n = 0;
+    for offset in 0 ..= text.len() {
+        indices.push(n);
+        if text.is_char_boundary(offset) {
+            n += 1;
+        }
+    }
+    indices
+}
+
+pub fn json(pages: &[PageSpans], texts: &[PageText], options: &PageTextOptions) -> String {
+    let pages: Vec<JsonPage> = pages.iter().zip(texts).map(|(page, (text, offsets))| {
+        let chars = char_indices(text);
+        // dehyphenation can take the last byte off a range
+        let char_offset = |range: &Range<usize>| [chars[range.start.min(text.len())], chars[range.end.min(text.len())]];
+        let index: HashMap<*const TextSpan, usize> = page.spans.iter().enumerate().map(|(i, span)| (span as *const _, i)).collect();
+        JsonPage {
+            document: page.document.as_deref(),
+            page: page.page_nr,
+            text,
+            lines: group_lines(&page.spans, options).iter().map(|line| JsonPageLine {
+                spans: line.spans.iter().map(|&span| index[&(span as *const _)]).collect(),
+                rect: [line.rect.min_x(), line.rect.min_y(), line.rect.max_x(), line.rect.max_y()],
+                baseline: line.baseline,
+            }).collect(),
+            spans: page.spans.iter().zip(offsets).map(|(span, offset)| JsonSpan {
+                text: &span.text,
+                rect: [span.rect.min_x(), span.rect.min_y(), span.rect.max_x(), span.rect.max_y()],
+                color: match span.color {
+                    Fill::Solid(r, g, b) => Some([r, g, b]),
+                    Fill::Pattern(_) => None,
+                },
+                font: span.font.as_ref().map(|font| font_name(&font.name)),
+                size: span.device_font_size(),
+                vertical: span.vertical(),
+                offset: offset.as_ref().map(char_offset),
+            }).collect(),
+            annotations: page.annotations.iter().map(|annot| JsonAnnotation {
+                subtype: &annot.subtype,
+                rect: [annot.rect.min_x(), annot.rect.min_y(), annot.rect.max_x(), annot.rect.max_y()],
+                contents: &annot.contents,
+            }).collect(),
+            links: page.links.iter().map(|link| JsonLink {
+                uri: match link.target {
+                    LinkTarget::Uri(ref uri) => Some(uri.as_str()),
+                    LinkTarget::Page(_) => None,
+                },
+                page: match link.target {
+                    LinkTarget::Page(page) => Some(page),
+                    LinkTarget::Uri(_) => None,
+                },
+                rect: [link.rect.min_x(), link.rect.min_y(), link.rect.max_x(), link.rect.max_y()],
+            }).collect(),
+        }
+    }).collect();
+    serde_json::to_string_pretty(&pages).expect("failed to serialize")
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    document: Option<&'a str>,
+    page: usize,
+    text: String,
+    // the union of the rects of its spans, in mm
+    rect: [f32; 4],
+    // y of the baseline in mm
+    baseline: f32,
+}
+
+// a JSON object per line of text, one per output line
+pub fn lines(pages: &[PageSpans], options: &PageTextOptions) -> String {
+    let mut out = String::new();
+    for page in pages {
+        for line in group_lines(&page.spans, options) {
+            let line = JsonLine {
+                document: page.document.as_deref(),
+                page: page.page_nr,
+                text: line.text(options.word_gap),
+                rect: [line.rect.min_x(), line.rect.min_y(), line.rect.max_x(), line.rect.max_y()],
+                baseline: line.baseline,
+            };
+            out += &serde_json::to_string(&line).expect("failed to serialize");
+            out.push('\n');
+        }
+    }
+    out
+}
+
+// hOCR wants integer coordinates; points keep enough precision
+const PT_PER_MM: f32 = 72. / 25.4;
+
+// device space already has its origin at the top left, like hOCR
+fn bbox(rect: RectF) -> String {
+    let pt = |v: f32| (v * PT_PER_MM).round() as i32;
+    format!("bbox {} {} {} {}", pt(rect.min_x()), pt(rect.min_y()), pt(rect.max_x()), pt(rect.max_y()))
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// the words of a horizontal span, with their share of its rect
+fn words(span: &TextSpan) -> Vec<(&str, RectF)> {
+    let rect = span.rect;
+    let x = |pos: f32| match span.width {
+        w if w > 0. => rect.min_x() + rect.width() * (pos / w).clamp(0., 1.),
+        _ => rect.min_x(),
+    };
+    // byte ranges of the words
+    let mut ranges = vec![];
+    let mut start = None;
+    for (i, c) in span.text.char_indices().chain(std::iter::once((span.text.len(), ' '))) {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                ranges.push((s, i));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+
+    let mut words = vec![];
+    let mut chars = span.chars.iter().peekable();
+    for (start, end) in ranges {
+        // the chars covering the word
+        while chars.next_if(|c| c.offset < start).is_some() {}
+        let (mut from, mut to) = (None, None);
+        while let Some(c) = chars.next_if(|c| c.offset < end) {
+            from.get_or_insert(c.pos);
+            to = Some(c.pos + c.width);
+        }
+        let word_rect = match (from, to) {
+            (Some(from), Some(to)) => RectF::from_points(
+                Vector2F::new(x(from), rect.min_y()),
+                Vector2F::new(x(to).max(x(from)), rect.max_y()),
+            ),
+            _ => rect,
+        };
+        words.push((&span.text[start..end], word_rect));
+    }
+    words
+}
+
+pub fn hocr(pages: &[PageSpans], options: &PageTextOptions) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\"/>\n");
+    out.push_str("<meta name=\"ocr-system\" content=\"pdf2text\"/>\n");
+    out.push_str("<meta name=\"ocr-capabilities\" content=\"ocr_page ocr_line ocrx_word\"/>\n");
+    out.push_str("</head>\n<body>\n");
+    let (mut line_nr, mut word_nr) = (0, 0);
+    // the page numbers start over in every document of a portfolio, the ids go on
+    for (page_id, page) in pages.iter().enumerate() {
+        writeln!(out, "<div class=\"ocr_page\" id=\"page_{}\" title=\"{}; ppageno {}\">", page_id, bbox(page.view_box), page.page_nr).unwrap();
+        for line in group_lines(&page.spans, options) {
+            line_nr += 1;
+            writeln!(out, "<span class=\"ocr_line\" id=\"line_{}\" title=\"{}\">", line_nr, bbox(line.rect)).unwrap();
+            for span in line.spans.iter() {
+                for (word, rect) in words(span) {
+                    word_nr += 1;
+                    writeln!(out, "<span class=\"ocrx_word\" id=\"word_{}\" title=\"{}\">{}</span>", word_nr, bbox(rect), escape(word)).unwrap();
+                }
+            }
+            out.push_str("</span>\n");
+        }
+        out.push_str("</div>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+// the tables of all pages as one, as bank statements and reports continue over pages
+pub fn csv(pages: &[PageSpans], options: &PageTextOptions) -> String {
+    let mut out = String::new();
+    for page in pages {
+        for row in table(&page.spans, options.word_gap) {
+            let cells: Vec<String> = row.iter().map(|cell| csv_field(cell)).collect();
+            out += &cells.join(",");
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.into()
+    }
+}
+
+// text this many times the size of the body is a heading of the level of its position
+const HEADING_SIZES: [f32; 3] = [1.8, 1.4, 1.15];
+
+// Markdown with paragraphs separated by blank lines. Lines set larger than the body text,
+// the size most of the text has, become headings; the larger, the higher their level.
+pub fn markdown(pages: &[PageSpans], options: &PageTextOptions) -> String {
+    let body = body_size(pages.iter().flat_map(|page| page.spans.iter()));
+    let level = |line: &TextLine| {
+        let size = line.spans.iter().map(|span| span.device_font_size()).fold(0., f32::max);
+        HEADING_SIZES.iter().position(|&f| size >= f * body).map(|i| i + 1)
+    };
+
+    let mut blocks: Vec<String> = vec![];
+    for page in pages {
+        for paragraph in group_paragraphs(group_lines(&page.spans, options), options.paragraph_gap) {
+            // a heading can sit right on top of its text, without the gap of a paragraph
+            let mut lines = paragraph.lines.into_iter().peekable();
+            while let Some(first) = lines.next() {
+                let heading = level(&first);
+                let mut part = Paragraph { rect: first.rect, lines: vec![first] };
+                while let Some(line) = lines.next_if(|line| level(line) == heading) {
+                    part.rect = part.rect.union_rect(line.rect);
+                    part.lines.push(line);
+                }
+                let text = part.text(options.dehyphenate, options.word_gap);
+                match heading {
+                    Some(level) => blocks.push(format!("{} {}", "#".repeat(level), text)),
+                    // a paragraph that starts like markup would turn into it
+                    None if text.starts_with(['#', '>', '-', '+', '*']) => blocks.push(format!("\\{}", text)),
+                    None if !text.is_empty() => blocks.push(text),
+                    None => {}
+                }
+            }
+        }
+    }
+    let mut out = blocks.join("\n\n");
+    out.push('\n');
+    out
+}
+
+// the font size of most of the characters, to half a millimeter
+fn body_size<'a>(spans: impl Iterator<Item=&'a TextSpan>) -> f32 {
+    let mut counts: HashMap<i32, usize> = HashMap::new();
+    for span in spans {
+        let size = (span.device_font_size() * 2.).round() as i32;
+        *counts.entry(size).or_default() += span.text.chars().filter(|c| !c.is_whitespace()).count();
+    }
+    match counts.into_iter().max_by_key(|&(size, count)| (count, -size)) {
+        Some((size, _)) if size > 0 => size as f32 / 2.,
+        _ => f32::INFINITY,
+    }
+}
+
+pub fn page_text(args: &Args, page: &PageSpans) -> String {
+    let mut text = String::new();
+    page_text_to(args, page, &mut text).unwrap();
+    text
+}
+
+// Write the text of the page into `out`. The body of the page is put together in one
+// piece, as reordering and normalizing it needs all lines, the annotations are written
+// one by one after it.
+fn page_text_to(args: &Args, page: &PageSpans, out: &mut impl fmt::Write) -> fmt::Result {
+    let spans = &page.spans;
+    let mut text = if args.layout {
+        layout_text(spans, page.view_box, Vector2F::new(args.cell_width, args.cell_height))
+    } else if args.paragraphs {
+        group_paragraphs(group_lines(spans, &args.text), args.text.paragraph_gap)
+            .iter()
+            .map(|p| p.text(args.text.dehyphenate, args.text.word_gap))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    } else {
+        items2text(spans, &args.text)
+    };
+    // the layout keeps the text where it is on the page
+    #[cfg(feature = "bidi")]
+    if !args.layout {
+        text = bidi::logical_order(&text);
+    }
+    // after dehyphenation, which needs the ones at the end of lines
+    if args.text.strip_soft_hyphens {
+        text.retain(|c| c != '\u{AD}');
+    }
+    // the layout places the text with spaces
+    if args.text.collapse_whitespace && !args.layout {
+        text = collapse_whitespace(&text);
+    }
+    match args.text.cjk_space {
+        CjkSpace::None => {}
+        _ if args.layout => {}
+        CjkSpace::Segment => text = space_ideographs(&text, false),
+        CjkSpace::PerChar => text = space_ideographs(&text, true),
+    }
+    out.write_str(&normalized(args, text))?;
+    for annot in page.annotations.iter() {
+        write!(out, "\n[{}] ", annot.subtype)?;
+        out.write_str(&normalized(args, annot.contents.clone()))?;
+    }
+    Ok(())
+}
+
+// every part is normalized on its own, they are separated by line breaks which don't combine
+#[cfg(feature = "unicode-normalization")]
+fn normalized(args: &Args, text: String) -> String {
+    match args.text.normalize {
+        Some(NormalizationForm::Nfc) => text.nfc().collect(),
+        Some(NormalizationForm::Nfd) => text.nfd().collect(),
+        None => text,
+    }
+}
+
+#[cfg(not(feature = "unicode-normalization"))]
+fn normalized(_args: &Args, text: String) -> String {
+    text
+}
+
+// the text of a page of the document written into `out`, after the pages before it
+fn document_page_to(args: &Args, page: &PageSpans, first: bool, document: &mut Option<String>, out: &mut impl fmt::Write) -> fmt::Result {
+    if !first {
+        out.write_str("\n")?;
+    }
+    // the documents of a portfolio are headed by their names
+    if page.document != *document {
+        document.clone_from(&page.document);
+        if let Some(ref name) = *document {
+            writeln!(out, "=== {} ===", name)?;
+        }
+    }
+    page_text_to(args, page, out)
+}
+
+// fmt::Write for a file or stdout, keeping the io error that stopped it
+struct IoWriter<W: io::Write> {
+    inner: W,
+    encoding: OutputEncoding,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> IoWriter<W> {
+    // the io error behind a failed write
+    fn result(&mut self, res: fmt::Result) -> io::Result<()> {
+        res.map_err(|_| self.error.take().unwrap_or_else(|| io::Error::other("failed to format text")))
+    }
+}
+
+impl<W: io::Write> fmt::Write for IoWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(&self.encoding.encode(s)).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+// the output file or stdout, starting with `header`
+fn text_output(args: &Args, header: &str) -> io::Result<IoWriter<Box<dyn io::Write>>> {
+    let inner: Box<dyn io::Write> = match args.output {
+        Some(ref out_path) => Box::new(io::BufWriter::new(std::fs::File::create(out_path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut out = IoWriter { inner, encoding: args.encoding, error: None };
+    out.inner.write_all(args.encoding.bom())?;
+    let res = out.write_str(header);
+    out.result(res)?;
+    Ok(out)
+}
+
+// the path of a page in an output directory, zero padded so the files sort by page and
+// counted from 0 like the pages everywhere else
+fn page_path(dir: &Path, format: Format, page: &PageSpans) -> PathBuf {
+    let extension = match format {
+        Format::Text => "txt",
+        Format::Json => "json",
+        Format::Lines => "jsonl",
+        Format::Hocr => "html",
+        Format::Csv => "csv",
+        Format::Md => "md",
+        #[cfg(feature = "debug-geometry")]
+        Format::Debug => "txt",
+    };
+    let name = format!("page-{:04}.{}", page.page_nr, extension);
+    match page.document.as_deref().and_then(|doc| Path::new(doc).file_stem()) {
+        // a NUL or a newline in the name of an embedded file can't go into a path
+        Some(doc) => dir.join(format!("{}-{}", doc.to_string_lossy().replace(char::is_control, "_"), name)),
+        None => dir.join(name),
+    }
+}
+
+// a directory, or a path that is meant to be one
+fn is_dir(path: &Path) -> bool {
+    path.is_dir() || path.to_string_lossy().ends_with(std::path::is_separator)
+}
+
+// a page in a file of its own
+fn write_page(args: &Args, dir: &Path, page: &PageSpans) {
+    let res = match args.format {
+        Format::Text => page_text(args, page),
+        Format::Json => json(std::slice::from_ref(page), &[items2text_with_offsets(&page.spans, &args.text)], &args.text),
+        Format::Lines => lines(std::slice::from_ref(page), &args.text),
+        Format::Hocr => hocr(std::slice::from_ref(page), &args.text),
+        Format::Csv => csv(std::slice::from_ref(page), &args.text),
+        Format::Md => markdown(std::slice::from_ref(page), &args.text),
+        #[cfg(feature = "debug-geometry")]
+        Format::Debug => geometry(&page.spans, &args.text),
+    };
+    write_file(args, &page_path(dir, args.format, page), &res);
+}
+
+// The text as asked for, to the output directory, the output file or stdout. Plain text and
+// the files of a directory are written as each page comes in, so the text of a document
+// isn't kept in memory. The other formats need all pages, and are written by finish.
+pub struct Output<'a> {
+    args: &'a Args,
+    sink: Sink,
+}
+
+enum Sink {
+    // with the number of pages written and the document of the last one
+    Text { out: IoWriter<Box<dyn io::Write>>, pages: usize, document: Option<String> },
+    Dir(PathBuf),
+    Pages(Vec<PageSpans>),
+}
+
+impl<'a> Output<'a> {
+    // the metadata and the table of contents asked for are written right away
+    pub fn new(args: &'a Args, file: &File<Vec<u8>>) -> Output<'a> {
+        let metadata = args.metadata.then(|| document_metadata(file).text());
+        let toc = args.toc.then(|| outline::toc(&document_outline(file)));
+
+        let sink = if let Some(dir) = args.output.as_deref().filter(|path| is_dir(path)) {
+            std::fs::create_dir_all(dir).expect("failed to create output directory");
+            if let Some(metadata) = metadata {
+                write_file(args, &dir.join("metadata.txt"), &metadata);
+            }
+            if let Some(toc) = toc {
+                write_file(args, &dir.join("toc.txt"), &toc);
+            }
+            Sink::Dir(dir.to_path_buf())
+        } else if matches!(args.format, Format::Text) {
+            let header = metadata.unwrap_or_default() + &toc.unwrap_or_default();
+            let out = text_output(args, &header).expect("failed to write to file");
+            Sink::Text { out, pages: 0, document: None }
+        } else {
+            Sink::Pages(vec![])
+        };
+        Output { args, sink }
+    }
+
+    pub fn page(&mut self, page: PageSpans) {
+        let args = self.args;
+        match self.sink {
+            Sink::Text { ref mut out, ref mut pages, ref mut document } => {
+                let res = document_page_to(args, &page, *pages == 0, document, out);
+                out.result(res).expect("failed to write to file");
+                *pages += 1;
+            }
+            Sink::Dir(ref dir) => write_page(args, dir, &page),
+            Sink::Pages(ref mut pages) => pages.push(page),
+        }
+    }
+
+    pub fn finish(self) {
+        let args = self.args;
+        let pages = match self.sink {
+            Sink::Text { mut out, .. } => {
+                // stdout gets a newline at the end, files don't
+                let res = if args.output.is_none() { out.write_str("\n") } else { Ok(()) };
+                out.result(res).and_then(|_| out.inner.flush()).expect("failed to write to file");
+                return;
+            }
+            Sink::Dir(_) => return,
+            Sink::Pages(pages) => pages,
+        };
+
+        let res = match args.format {
+            // written page by page
+            Format::Text => unreachable!(),
+            Format::Json => {
+                let texts: Vec<PageText> = pages.iter().map(|page| items2text_with_offsets(&page.spans, &args.text)).collect();
+                json(&pages, &texts, &args.text)
+            }
+            Format::Lines => lines(&pages, &args.text),
+            Format::Hocr => hocr(&pages, &args.text),
+            Format::Csv => csv(&pages, &args.text),
+            Format::Md => markdown(&pages, &args.text),
+            #[cfg(feature = "debug-geometry")]
+            Format::Debug => pages.iter().map(|page| geometry(&page.spans, &args.text)).collect::<Vec<_>>().join("\n"),
+        };
+
+        if let Some(ref out_path) = args.output {
+            write_file(args, out_path, &res);
+        } else {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(args.encoding.bom())
+                .and_then(|_| stdout.write_all(&args.encoding.encode(&res)))
+                .and_then(|_| stdout.write_all(&args.encoding.encode("\n")))
+                .expect("failed to write to stdout");
+        }
+    }
+}
+
+// `text` in the output encoding
+fn write_file(args: &Args, path: &Path, text: &str) {
+    let mut data = args.encoding.bom().to_vec();
+    data.extend_from_slice(&args.encoding.encode(text));
+    std::fs::write(path, data).expect("failed to write to file");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use pdf_render::{testpdf, StandardCache};
+    use crate::stats::Stats;
+    use crate::tests::span;
+    use crate::document_pages;
+
+    // the text of a page of `spans`, written with the options of `args`
+    fn text_of(args: &[&str], spans: Vec<TextSpan>) -> String {
+        let args = Args::parse_from(["pdf2text"].iter().chain(args).chain(&["in.pdf"]));
+        let page = PageSpans {
+            document: None,
+            page_nr: 0,
+            view_box: RectF::default(),
+            spans,
+            annotations: vec![],
+            links: vec![],
+        };
+        let mut text = String::new();
+        page_text_to(&args, &page, &mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn soft_hyphens() {
+        let spans = || vec![span("co\u{AD}operate", 10., 20., 4.)];
+        assert_eq!(text_of(&[], spans()), "cooperate");
+        assert_eq!(text_of(&["--keep-soft-hyphens"], spans()), "co\u{AD}operate");
+    }
+
+    #[test]
+    fn word_gap_in_paragraphs() {
+        // a gap of 0.8mm, most of the 1mm space of the 4mm text
+        let spans = || vec![span("a", 10., 20., 4.), span("b", 12.8, 20., 4.)];
+        assert_eq!(text_of(&["--paragraphs"], spans()), "a b");
+        assert_eq!(text_of(&["--paragraphs", "--word-gap", "1"], spans()), "ab");
+    }
+
+    #[test]
+    fn paragraph_gap_in_paragraphs() {
+        // a leading of 5mm, then twice that
+        let spans = || ["one", "two", "three"].iter()
+            .zip([20., 25., 35.])
+            .map(|(text, baseline)| span(text, 10., baseline, 4.))
+            .collect::<Vec<_>>();
+        assert_eq!(text_of(&["--paragraphs"], spans()), "one two\n\nthree");
+        assert_eq!(text_of(&["--paragraphs", "--paragraph-gap", "2.5"], spans()), "one two three");
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn normalization() {
+        // an e with a combining acute, and a precomposed one
+        let spans = || vec![span("Caf\u{65}\u{301} caf\u{E9}", 10., 20., 4.)];
+        assert_eq!(text_of(&[], spans()), "Caf\u{65}\u{301} caf\u{E9}");
+        assert_eq!(text_of(&["--normalize", "nfc"], spans()), "Caf\u{E9} caf\u{E9}");
+        assert_eq!(text_of(&["--normalize", "nfd"], spans()), "Caf\u{65}\u{301} caf\u{65}\u{301}");
+    }
+
+    #[test]
+    fn hocr_page() {
+        let page = PageSpans {
+            document: None,
+            page_nr: 0,
+            view_box: RectF::new(Vector2F::zero(), Vector2F::new(100., 50.)),
+            spans: vec![
+                span("Fish & chips", 10., 20., 4.),
+                span("<b>", 10., 30., 4.),
+            ],
+            annotations: vec![],
+            links: vec![],
+        };
+        // in points, the words of a span get their share of it
+        let expected = concat!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\"/>\n",
+            "<meta name=\"ocr-system\" content=\"pdf2text\"/>\n",
+            "<meta name=\"ocr-capabilities\" content=\"ocr_page ocr_line ocrx_word\"/>\n",
+            "</head>\n<body>\n",
+            "<div class=\"ocr_page\" id=\"page_0\" title=\"bbox 0 0 283 142; ppageno 0\">\n",
+            "<span class=\"ocr_line\" id=\"line_1\" title=\"bbox 28 45 96 57\">\n",
+            "<span class=\"ocrx_word\" id=\"word_1\" title=\"bbox 28 45 51 57\">Fish</span>\n",
+            "<span class=\"ocrx_word\" id=\"word_2\" title=\"bbox 57 45 62 57\">&amp;</span>\n",
+            "<span class=\"ocrx_word\" id=\"word_3\" title=\"bbox 68 45 96 57\">chips</span>\n",
+            "</span>\n",
+            "<span class=\"ocr_line\" id=\"line_2\" title=\"bbox 28 74 45 85\">\n",
+            "<span class=\"ocrx_word\" id=\"word_4\" title=\"bbox 28 74 45 85\">&lt;b&gt;</span>\n",
+            "</span>\n",
+            "</div>\n",
+            "</body>\n</html>\n",
+        );
+        assert_eq!(hocr(&[page], &PageTextOptions::default()), expected);
+    }
+
+    #[test]
+    fn hocr_of_two_documents() {
+        // the same page as the first of two documents, like in a portfolio
+        let [type3, to_unicode] = testpdf::abc_font(4);
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 100] /Resources << /Font << /F1 4 0 R >> >> /Contents 6 0 R >>",
+            &[&type3, &to_unicode, &testpdf::stream(b"BT /F1 10 Tf 10 80 Td (AB) Tj ET")],
+        );
+        let file = File::from_data(data).unwrap();
+        let args = Args::parse_from(["pdf2text", "--quiet", "--format", "hocr", "in.pdf"]);
+        let std = StandardCache::new(PathBuf::from("."));
+        let mut pages = vec![];
+        for document in ["a.pdf", "b.pdf"] {
+            document_pages(&args, &file, Some(document), &std, &mut Stats::default(), &mut |page| pages.push(page));
+        }
+        let page = |id: usize| format!(concat!(
+            "<div class=\"ocr_page\" id=\"page_{}\" title=\"bbox 0 0 200 100; ppageno 0\">\n",
+            "<span class=\"ocr_line\" id=\"line_{}\" title=\"bbox 10 10 20 20\">\n",
+            "<span class=\"ocrx_word\" id=\"word_{}\" title=\"bbox 10 10 20 20\">AB</span>\n",
+            "</span>\n",
+            "</div>\n",
+        ), id, id + 1, id + 1);
+        let expected = String::from(concat!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\"/>\n",
+            "<meta name=\"ocr-system\" content=\"pdf2text\"/>\n",
+            "<meta name=\"ocr-capabilities\" content=\"ocr_page ocr_line ocrx_word\"/>\n",
+            "</head>\n<body>\n",
+        )) + &page(0) + &page(1) + "</body>\n</html>\n";
+        assert_eq!(hocr(&pages, &args.text), expected);
+    }
+
+    #[test]
+    fn json_colors() {
+        let (mut red, mut blue) = (span("red", 10., 20., 4.), span("blue", 10., 30., 4.));
+        red.color = Fill::Solid(1., 0., 0.);
+        blue.color = Fill::Solid(0., 0., 1.);
+        let page = PageSpans {
+            document: None,
+            page_nr: 0,
+            view_box: RectF::default(),
+            spans: vec![red, blue],
+            annotations: vec![],
+            links: vec![],
+        };
+        let options = PageTextOptions::default();
+        let text = items2text_with_offsets(&page.spans, &options);
+        let json: serde_json::Value = serde_json::from_str(&json(&[page], &[text], &options)).unwrap();
+        let colors: Vec<_> = json[0]["spans"].as_array().unwrap().iter()
+            .map(|span| (span["text"].as_str().unwrap(), span["color"].clone()))
+            .collect();
+        assert_eq!(colors, [("red", serde_json::json!([1., 0., 0.])), ("blue", serde_json::json!([0., 0., 1.]))]);
+    }
+
+    #[test]
+    fn markdown_headings() {
+        // a title, a section, and the body text in two paragraphs
+        let page = PageSpans {
+            document: None,
+            page_nr: 0,
+            view_box: RectF::default(),
+            spans: vec![
+                span("Report", 10., 20., 8.),
+                span("Summary", 10., 32., 6.),
+                span("The first line", 10., 40., 4.),
+                span("the second", 10., 45., 4.),
+                span("and the third.", 10., 50., 4.),
+                span("Another paragraph.", 10., 65., 4.),
+            ],
+            annotations: vec![],
+            links: vec![],
+        };
+        // 8mm is twice the body size and 6mm one and a half times
+        assert_eq!(markdown(&[page], &PageTextOptions::default()), "# Report\n\n## Summary\n\nThe first line the second and the third.\n\nAnother paragraph.\n");
+    }
+
+    #[test]
+    fn json_lines() {
+        // drawn out of order, with a raised footnote marker on the first line
+        let mut marker = span("1", 20., 20., 2.);
+        marker.rise = 1.;
+        marker.font_rect = marker.font_rect + Vector2F::new(0., -1.);
+        let page = PageSpans {
+            document: None,
+            page_nr: 0,
+            view_box: RectF::default(),
+            spans: vec![span("second", 10., 26., 4.), span("First", 10., 20., 4.), marker],
+            annotations: vec![],
+            links: vec![],
+        };
+        let options = PageTextOptions::default();
+        let text = items2text_with_offsets(&page.spans, &options);
+        let json: serde_json::Value = serde_json::from_str(&json(&[page], &[text], &options)).unwrap();
+        assert_eq!(json[0]["lines"], serde_json::json!([
+            { "spans": [1, 2], "rect": [10., 16., 21., 20.], "baseline": 20. },
+            { "spans": [0], "rect": [10., 22., 22., 26.], "baseline": 26. },
+        ]));
+    }
+
+    #[test]
+    fn csv_fields() {
+        assert_eq!(csv_field("plain text"), "plain text");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("1,50"), "\"1,50\"");
+        assert_eq!(csv_field("a \"quote\""), "\"a \"\"quote\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("cr\r"), "\"cr\r\"");
+    }
+
+    #[test]
+    fn page_paths() {
+        let page = |document: Option<&str>| PageSpans {
+            document: document.map(String::from),
+            page_nr: 1,
+            view_box: RectF::default(),
+            spans: vec![],
+            annotations: vec![],
+            links: vec![],
+        };
+        let dir = Path::new("out");
+        assert_eq!(page_path(dir, Format::Text, &page(None)), Path::new("out/page-0001.txt"));
+        assert_eq!(page_path(dir, Format::Json, &page(Some("docs/report.pdf"))), Path::new("out/report-page-0001.json"));
+        assert_eq!(page_path(dir, Format::Text, &page(Some("a\0b\n.pdf"))), Path::new("out/a_b_-page-0001.txt"));
+    }
+
+    #[test]
+    fn streamed_text() {
+        let path = std::env::temp_dir().join(format!("pdf2text-streamed-{}.txt", std::process::id()));
+        let args = Args::parse_from(["pdf2text", "-o", path.to_str().unwrap(), "in.pdf"]);
+        let file = File::from_data(testpdf::pdf_with_page(b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] >>", &[])).unwrap();
+        let page = |document: &str, text: &str| PageSpans {
+            document: Some(document.into()),
+            page_nr: 0,
+            view_box: RectF::default(),
+            spans: vec![span(text, 10., 20., 4.)],
+            annotations: vec![],
+            links: vec![],
+        };
+        let mut output = Output::new(&args, &file);
+        output.page(page("a.pdf", "one"));
+        output.page(page("a.pdf", "two"));
+        output.page(page("b.pdf", "three"));
+        output.finish();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // a page after the other, and a heading where another document of a portfolio starts
+        assert_eq!(text, "=== a.pdf ===\none\ntwo\n=== b.pdf ===\nthree");
+    }
+}
@@ -0,0 +1,129 @@
+use pdf::error::PdfError;
+use pdf::object::{Object, Resolve, Stream};
+use pdf::primitive::{Dictionary, Primitive};
+use pdf_render::text_string;
+
+use crate::objects::{dict, entry, walk_name_tree};
+
+// a document embedded in a portfolio
+pub struct EmbeddedPdf {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+// a portfolio has a /Collection in its catalog
+pub fn is_portfolio(catalog: &Dictionary) -> bool {
+    catalog.get("Collection").is_some()
+}
+
+// all PDFs in the /EmbeddedFiles name tree of the catalog
+pub fn embedded_pdfs(resolve: &impl Resolve, catalog: &Dictionary) -> Vec<EmbeddedPdf> {
+    let mut files = vec![];
+    if let Some(tree) = entry(resolve, catalog, "Names").and_then(|names| entry(resolve, &names, "EmbeddedFiles")) {
+        // file specifications by name
+        walk_name_tree(resolve, &tree, &mut |key, spec| {
+            let key = match *key {
+                Primitive::String(ref s) => text_string(s.as_bytes()),
+                _ => String::new(),
+            };
+            if let Some(spec) = dict(resolve, spec) {
+                match embedded_file(resolve, &spec, key) {
+                    Ok(Some(file)) => files.push(file),
+                    Ok(None) => {}
+                    Err(e) => eprintln!("failed to read embedded file: {:?}", e),
+                }
+            }
+        });
+    }
+    files
+}
+
+// prefer the unicode file name, then the plain one, then the name tree key
+fn file_name(spec: &Dictionary, key: String) -> String {
+    ["UF", "F"].iter()
+        .filter_map(|&k| match spec.get(k) {
+            Some(Primitive::String(s)) => Some(text_string(s.as_bytes())),
+            _ => None,
+        })
+        .next()
+        .unwrap_or(key)
+}
+
+fn embedded_file(resolve: &impl Resolve, spec: &Dictionary, key: String) -> Result<Option<EmbeddedPdf>, PdfError> {
+    let name = file_name(spec, key);
+    let stream = match entry(resolve, spec, "EF") {
+        Some(ef) => match ef.get("UF").or_else(|| ef.get("F")) {
+            Some(stream) => stream.clone(),
+            None => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+    let stream = Stream::<()>::from_primitive(stream, resolve)?;
+    let data = stream.data(resolve)?.to_vec();
+
+    // portfolios can also hold other kinds of files
+    let header = &data[.. data.len().min(1024)];
+    if !header.windows(5).any(|w| w == b"%PDF-") {
+        return Ok(None);
+    }
+    Ok(Some(EmbeddedPdf { name, data }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use clap::Parser;
+    use pdf::file::File;
+    use pdf::primitive::PdfString;
+    use pdf_render::{testpdf, StandardCache};
+    use crate::stats::Stats;
+    use crate::{document_pages, Args};
+
+    fn spec(entries: &[(&str, &[u8])]) -> Dictionary {
+        let mut spec = Dictionary::new();
+        for &(key, value) in entries {
+            spec.insert(key, Primitive::String(PdfString::new(value.into())));
+        }
+        spec
+    }
+
+    #[test]
+    fn file_names() {
+        // UTF-16 with a byte order mark, and PDFDocEncoding
+        let unicode = spec(&[("UF", b"\xfe\xff\x00r\x00\xe9\x00s\x00.\x00p\x00d\x00f"), ("F", b"res.pdf")]);
+        assert_eq!(file_name(&unicode, "key".into()), "r\u{e9}s.pdf");
+        assert_eq!(file_name(&spec(&[("F", b"caf\xe9\x84.pdf")]), "key".into()), "caf\u{e9}\u{2014}.pdf");
+        assert_eq!(file_name(&spec(&[]), "key".into()), "key");
+    }
+
+    // a pdf of one page with `content` in the font of testpdf::abc_font
+    fn child(content: &[u8]) -> Vec<u8> {
+        let [type3, to_unicode] = testpdf::abc_font(4);
+        testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> >> /Contents 6 0 R >>",
+            &[&type3, &to_unicode, &testpdf::stream(content)],
+        )
+    }
+
+    #[test]
+    fn two_documents() {
+        // the cover page of the portfolio has no text of its own
+        let data = testpdf::pdf_with_objects(&[
+            b"<< /Type /Catalog /Pages 2 0 R /Collection << /Type /Collection >> /Names << /EmbeddedFiles 4 0 R >> >>",
+            b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] >>",
+            b"<< /Names [(1) 5 0 R (2) 7 0 R] >>",
+            b"<< /Type /Filespec /F (one.pdf) /EF << /F 6 0 R >> >>",
+            &testpdf::stream_with("/Type /EmbeddedFile", &child(b"BT /F1 10 Tf 10 10 Td (AB) Tj ET")),
+            b"<< /Type /Filespec /F (two.pdf) /EF << /F 8 0 R >> >>",
+            &testpdf::stream_with("/Type /EmbeddedFile", &child(b"BT /F1 10 Tf 10 10 Td (CA) Tj ET")),
+        ]);
+        let file = File::from_data(data).unwrap();
+        let args = Args::parse_from(["pdf2text", "--quiet", "in.pdf"]);
+        let mut pages = vec![];
+        document_pages(&args, &file, None, &StandardCache::new(PathBuf::from(".")), &mut Stats::default(), &mut |page| pages.push(page));
+        let texts: Vec<_> = pages.iter().map(|page| (page.document.as_deref(), page.spans[0].text.as_str())).collect();
+        assert_eq!(texts, [(Some("one.pdf"), "AB"), (Some("two.pdf"), "CA")]);
+    }
+}
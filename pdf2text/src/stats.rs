@@ -0,0 +1,149 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use pdf_render::{DecodeSource, TextSpan};
+
+use crate::output::PageSpans;
+
+// a quick quality assessment of an extraction, printed with --stats
+#[derive(Default)]
+pub struct Stats {
+    pages: usize,
+    chars: usize,
+    fonts: BTreeSet<String>,
+    // spans whose text had to be guessed from the raw codes
+    low_confidence: usize,
+    // pages without any text, most likely scanned images
+    empty_pages: Vec<usize>,
+    // pages that couldn't be read and are missing from the output
+    failed_pages: Vec<usize>,
+}
+impl Stats {
+    pub fn add_page(&mut self, page_nr: usize, spans: &[TextSpan]) {
+        self.pages += 1;
+        if spans.is_empty() {
+            self.empty_pages.push(page_nr);
+        }
+        for span in spans {
+            self.chars += span.text.chars().count();
+            if let Some(ref font) = span.font {
+                self.fonts.insert(font.name.clone());
+            }
+            if span.decode_source() == Some(DecodeSource::RawGuess) {
+                self.low_confidence += 1;
+            }
+        }
+    }
+    pub fn add_failed_page(&mut self, page_nr: usize) {
+        self.failed_pages.push(page_nr);
+    }
+    pub fn failed(&self) -> bool {
+        !self.failed_pages.is_empty()
+    }
+    pub fn print(&self) {
+        eprint!("{}", self.summary());
+    }
+    fn summary(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "pages: {}", self.pages).unwrap();
+        writeln!(out, "characters: {}", self.chars).unwrap();
+        writeln!(out, "fonts ({}):", self.fonts.len()).unwrap();
+        for font in self.fonts.iter() {
+            writeln!(out, "  {}", font).unwrap();
+        }
+        writeln!(out, "low-confidence spans: {}", self.low_confidence).unwrap();
+        if self.empty_pages.is_empty() {
+            writeln!(out, "pages without text: none").unwrap();
+        } else {
+            let pages: Vec<String> = self.empty_pages.iter().map(|n| n.to_string()).collect();
+            writeln!(out, "pages without text (likely scanned): {}", pages.join(", ")).unwrap();
+        }
+        if !self.failed_pages.is_empty() {
+            let pages: Vec<String> = self.failed_pages.iter().map(|n| n.to_string()).collect();
+            writeln!(out, "pages that failed: {}", pages.join(", ")).unwrap();
+        }
+        out
+    }
+}
+
+// A table of the pages with how much text each has, and how many have any, printed with
+// --check. Pages that failed to analyze were reported already and aren't listed.
+pub fn check(pages: &[PageSpans]) {
+    eprint!("{}", check_table(pages));
+}
+
+fn check_table(pages: &[PageSpans]) -> String {
+    let mut out = String::new();
+    writeln!(out, "page\tspans\tchars\ttext").unwrap();
+    let mut with_text = 0;
+    for page in pages {
+        let chars: usize = page.spans.iter().map(|span| span.text.chars().filter(|c| !c.is_whitespace()).count()).sum();
+        if chars > 0 {
+            with_text += 1;
+        }
+        let name = match page.document {
+            Some(ref document) => format!("{}:{}", document, page.page_nr),
+            None => page.page_nr.to_string(),
+        };
+        writeln!(out, "{}\t{}\t{}\t{}", name, page.spans.len(), chars, if chars > 0 { "yes" } else { "no (likely scanned)" }).unwrap();
+    }
+    writeln!(out, "{} of {} pages have text", with_text, pages.len()).unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use clap::Parser;
+    use pdf::file::File;
+    use pdf_render::{testpdf, StandardCache};
+    use crate::{document_pages, Args};
+
+    #[test]
+    fn summary() {
+        // a page of text, one of them in a Type3 font without a ToUnicode or an encoding whose
+        // text is a guess, a page with nothing but an image and one that can't be read
+        let [type3, to_unicode] = testpdf::abc_font(6);
+        let data = testpdf::pdf_with_objects(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>",
+            b"<< /Type /Pages /Kids [3 0 R 4 0 R 5 0 R] /Count 3 /MediaBox [0 0 200 200] /Resources << /Font << /F1 6 0 R /F2 8 0 R >> >> >>",
+            b"<< /Type /Page /Parent 2 0 R /Contents 9 0 R >>",
+            b"<< /Type /Page /Parent 2 0 R /Contents 10 0 R >>",
+            b"<< /Type /Page /Parent 2 0 R /Contents 11 0 R >>",
+            &type3,
+            &to_unicode,
+            b"<< /Type /Font /Subtype /Type3 /Name /T3 /FontBBox [0 0 1000 1000] /FontMatrix [0.001 0 0 0.001 0 0] /CharProcs << >> /FirstChar 65 /LastChar 66 /Widths [500 500] >>",
+            &testpdf::stream(b"BT /F1 10 Tf 10 10 Td (ABC) Tj /F2 10 Tf 0 20 Td (AB) Tj ET"),
+            &testpdf::stream(b"q 200 0 0 200 0 0 cm BI /W 1 /H 1 /CS /G /BPC 8 ID \x80 EI Q"),
+            &testpdf::stream_with("/Filter /FlateDecode", b"BT /F1 10 Tf 10 10 Td (AB) Tj ET"),
+        ]);
+        let file = File::from_data(data).unwrap();
+        let args = Args::parse_from(["pdf2text", "--quiet", "--stats", "--undecoded", "replacement", "in.pdf"]);
+        let mut stats = Stats::default();
+        document_pages(&args, &file, None, &StandardCache::new(PathBuf::from(".")), &mut stats, &mut |_| {});
+        // the guessed text is two replacement characters, the font of abc_font has no name of its own
+        assert_eq!(stats.summary(), "pages: 2\ncharacters: 5\nfonts (2):\n  T3\n  Type3\nlow-confidence spans: 1\npages without text (likely scanned): 1\npages that failed: 2\n");
+    }
+
+    #[test]
+    fn check_a_scanned_page() {
+        // a page of text, and one with nothing but an image
+        let [type3, to_unicode] = testpdf::abc_font(5);
+        let data = testpdf::pdf_with_objects(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>",
+            b"<< /Type /Pages /Kids [3 0 R 4 0 R] /Count 2 /MediaBox [0 0 200 200] /Resources << /Font << /F1 5 0 R >> >> >>",
+            b"<< /Type /Page /Parent 2 0 R /Contents 7 0 R >>",
+            b"<< /Type /Page /Parent 2 0 R /Contents 8 0 R >>",
+            &type3,
+            &to_unicode,
+            &testpdf::stream(b"BT /F1 10 Tf 10 10 Td (ABC) Tj 0 20 Td (CAB) Tj ET"),
+            &testpdf::stream(b"q 200 0 0 200 0 0 cm BI /W 1 /H 1 /CS /G /BPC 8 ID \x80 EI Q"),
+        ]);
+        let file = File::from_data(data).unwrap();
+        let args = Args::parse_from(["pdf2text", "--quiet", "--check", "in.pdf"]);
+        let mut pages = vec![];
+        document_pages(&args, &file, None, &StandardCache::new(PathBuf::from(".")), &mut Stats::default(), &mut |page| pages.push(page));
+        assert_eq!(check_table(&pages), "page\tspans\tchars\ttext\n0\t2\t6\tyes\n1\t0\t0\tno (likely scanned)\n1 of 2 pages have text\n");
+    }
+}
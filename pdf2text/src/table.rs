@@ -0,0 +1,86 @@
+use pathfinder_geometry::rect::RectF;
+use pdf_render::TextSpan;
+
+use crate::columns::split_columns;
+use crate::lines::{group_rows, TextLine};
+
+// The spans as a table: a row for every line, split into cells at the gutters that no line
+// crosses. A line that leaves the first column empty is taken as the wrapped rest of the
+// row before it, which also joins rows that really have no first cell to the one above.
+pub fn table(spans: &[TextSpan], word_gap: f32) -> Vec<Vec<String>> {
+    let lines = group_rows(spans);
+    let mut heights: Vec<f32> = lines.iter().map(|line| line.height()).filter(|&h| h > 0.).collect();
+    heights.sort_by(f32::total_cmp);
+    let height = heights.get(heights.len() / 2).copied().unwrap_or(1.);
+
+    let items: Vec<(RectF, (usize, &TextSpan))> = lines.iter().enumerate()
+        .flat_map(|(i, line)| line.spans.iter().map(move |&span| (span.rect, (i, span))))
+        .collect();
+    let columns = split_columns(items, height, None);
+
+    // spans by line and column, still left to right
+    let mut cells: Vec<Vec<Vec<&TextSpan>>> = vec![vec![vec![]; columns.len()]; lines.len()];
+    for (c, column) in columns.into_iter().enumerate() {
+        for (_, (line, span)) in column {
+            cells[line][c].push(span);
+        }
+    }
+
+    let mut rows: Vec<Vec<String>> = vec![];
+    for line in cells {
+        let texts: Vec<String> = line.into_iter().map(|spans| cell_text(spans, word_gap)).collect();
+        if texts.iter().all(String::is_empty) {
+            continue;
+        }
+        match rows.last_mut() {
+            Some(row) if texts[0].is_empty() => {
+                for (cell, text) in row.iter_mut().zip(texts) {
+                    if !cell.is_empty() && !text.is_empty() {
+                        cell.push(' ');
+                    }
+                    *cell += &text;
+                }
+            }
+            _ => rows.push(texts),
+        }
+    }
+    rows
+}
+
+fn cell_text(spans: Vec<&TextSpan>, word_gap: f32) -> String {
+    let rect = match spans.iter().map(|span| span.rect).reduce(|a, b| a.union_rect(b)) {
+        Some(rect) => rect,
+        None => return String::new(),
+    };
+    let line = TextLine { spans, rect, baseline: rect.max_y() };
+    line.text(word_gap).trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use pathfinder_geometry::rect::RectF;
+
+    use crate::output::{csv, PageSpans};
+    use crate::tests::span;
+    use crate::PageTextOptions;
+
+    #[test]
+    fn three_by_three() {
+        let spans = vec![
+            span("Name", 10., 20., 4.), span("Qty", 50., 20., 4.), span("Note", 80., 20., 4.),
+            span("Apple", 10., 26., 4.), span("3", 50., 26., 4.), span("red, sweet", 80., 26., 4.),
+            span("Pear", 10., 32., 4.), span("12", 50., 32., 4.), span("ripe", 80., 32., 4.),
+            // the note of the pear wraps onto a line of its own
+            span("and soft", 80., 38., 4.),
+        ];
+        let page = PageSpans {
+            document: None,
+            page_nr: 0,
+            view_box: RectF::default(),
+            spans,
+            annotations: vec![],
+            links: vec![],
+        };
+        assert_eq!(csv(&[page], &PageTextOptions::default()), "Name,Qty,Note\nApple,3,\"red, sweet\"\nPear,12,ripe and soft\n");
+    }
+}
@@ -25,6 +25,46 @@ pub trait Backend {
     }
     fn get_font(&mut self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError>;
     fn add_text(&mut self, span: TextSpan);
+    fn missing_font(&self) -> MissingFont {
+        MissingFont::Drop
+    }
+    fn undecoded(&self) -> Undecoded {
+        Undecoded::Drop
+    }
+    fn tj_spacing(&self) -> TjSpacing {
+        TjSpacing::Relative
+    }
+}
+// what to do with text drawn in a font that could not be loaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissingFont {
+    // leave the text out
+    Drop,
+    // emit U+FFFD for every byte
+    Placeholder,
+    // decode the bytes as StandardEncoding
+    Guess,
+}
+// what to emit for a code the font has no unicode for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Undecoded {
+    // nothing
+    Drop,
+    // U+FFFD
+    Replacement,
+    // the code in hex, like <1F> or <012A> for two byte codes
+    Hex,
+    // the private use character U+F0000 + code, for tools that map them later
+    PrivateUse,
+}
+// how the adjustments in a TJ array turn into spaces
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TjSpacing {
+    // a space for every adjustment wider than 0.1 text space units
+    Fixed,
+    // judged by the width of a space in the font: kerning, a space, or a tab for a jump of several spaces.
+    // the default, falling back to Fixed for fonts without a width for the space
+    Relative,
 }
 #[derive(Clone)]
 pub enum DrawMode {
@@ -15,7 +15,7 @@ use pathfinder_content::{
 
 use super::{fontentry::FontEntry};
 use super::image::load_image;
-use super::font::{load_font, StandardCache};
+use super::font::{load_font, FontOverrides, StandardCache};
 use globalcache::{sync::SyncCache, ValueSize};
 
 #[derive(Clone)]
@@ -34,6 +34,7 @@ pub struct Cache {
     fonts: Arc<SyncCache<usize, Option<Arc<FontEntry>>>>,
     images: Arc<SyncCache<Ref<XObject>, ImageResult>>,
     std: StandardCache,
+    overrides: FontOverrides,
     missing_fonts: Vec<Name>,
 }
 impl Cache {
@@ -48,17 +49,26 @@ impl Cache {
         if !standard_fonts.is_dir() {
             panic!("STANDARD_FONTS (or fonts/) is not directory.");
         }
+        Cache::with_standard_fonts(StandardCache::new(standard_fonts))
+    }
+    pub fn with_standard_fonts(std: StandardCache) -> Cache {
         Cache {
             fonts: SyncCache::new(),
             images: SyncCache::new(),
-            std: StandardCache::new(standard_fonts),
+            std,
+            overrides: FontOverrides::new(),
             missing_fonts: Vec::new(),
         }
     }
+    // decode some codes as given, see FontOverrides
+    pub fn with_overrides(mut self, overrides: FontOverrides) -> Cache {
+        self.overrides = overrides;
+        self
+    }
     pub fn get_font(&mut self, pdf_font: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, > {
         let mut error = None;
         let val = self.fonts.get(&**pdf_font as *const PdfFont as usize, || 
-            match load_font(pdf_font, resolve, &mut self.std) {
+            match load_font(pdf_font, resolve, &self.std, &self.overrides) {
                 Ok(Some(f)) => Some(Arc::new(f)),
                 Ok(None) => {
                     if let Some(ref name) = pdf_font.name {
@@ -0,0 +1,277 @@
+//! Embedded CMap streams, as used for the /Encoding of composite fonts.
+//!
+//! Only what is needed to map character codes to CIDs is read: `begincodespacerange`,
+//! `begincidrange`, `begincidchar` and the `/WMode`. The notdef ranges are ignored, and `usecmap` is not
+//! followed, so a CMap that builds on a predefined one only has its own mappings.
+//!
+//! Of the predefined CMaps, only the codespaces of those that mix one and two byte codes
+//! are known, to split strings in the right places.
+
+pub struct CodeMap {
+    // (low, high) byte sequences of equal length
+    codespace: Vec<(Vec<u8>, Vec<u8>)>,
+    // first code, last code, first cid
+    cids: Vec<(u32, u32, u32)>,
+    // /WMode 1, the glyphs advance downwards
+    pub vertical: bool,
+}
+
+enum Token<'a> {
+    Hex(Vec<u8>),
+    Number(u32),
+    Word(&'a [u8]),
+}
+
+impl CodeMap {
+    pub fn parse(data: &[u8]) -> Option<CodeMap> {
+        let tokens = tokens(data);
+        let mut codespace = vec![];
+        let mut cids = vec![];
+        let mut vertical = false;
+        let mut i = 0;
+        while i < tokens.len() {
+            match tokens[i] {
+                Token::Word(b"begincodespacerange") => {
+                    i += 1;
+                    while let (Some(Token::Hex(lo)), Some(Token::Hex(hi))) = (tokens.get(i), tokens.get(i + 1)) {
+                        if lo.len() == hi.len() && !lo.is_empty() && lo.len() <= 4 {
+                            codespace.push((lo.clone(), hi.clone()));
+                        }
+                        i += 2;
+                    }
+                }
+                Token::Word(b"begincidrange") => {
+                    i += 1;
+                    while let (Some(Token::Hex(lo)), Some(Token::Hex(hi)), Some(&Token::Number(cid))) = (tokens.get(i), tokens.get(i + 1), tokens.get(i + 2)) {
+                        if let (Some(lo), Some(hi)) = (code(lo), code(hi)) {
+                            cids.push((lo, hi, cid));
+                        }
+                        i += 3;
+                    }
+                }
+                Token::Word(b"begincidchar") => {
+                    i += 1;
+                    while let (Some(Token::Hex(c)), Some(&Token::Number(cid))) = (tokens.get(i), tokens.get(i + 1)) {
+                        if let Some(c) = code(c) {
+                            cids.push((c, c, cid));
+                        }
+                        i += 2;
+                    }
+                }
+                Token::Word(b"WMode") => {
+                    vertical = matches!(tokens.get(i + 1), Some(&Token::Number(1)));
+                    i += 1;
+                }
+                _ => i += 1,
+            }
+        }
+        if codespace.is_empty() {
+            return None;
+        }
+        codespace.sort_by_key(|(lo, _)| lo.len());
+        Some(CodeMap { codespace, cids, vertical })
+    }
+
+    // The codespace of a predefined CMap that mixes one and two byte codes, without any
+    // cids. Most others, like Identity-H or UniGB-UCS2-H, only have two byte codes, which
+    // is how composite fonts are read without a code map.
+    pub fn predefined(name: &str) -> Option<CodeMap> {
+        let vertical = name.ends_with("-V");
+        let family = name.strip_suffix("-H").or_else(|| name.strip_suffix("-V"))?;
+        let codespace: &[(&[u8], &[u8])] = match family {
+            "GB-EUC" | "KSC-EUC" => &[(b"\x00", b"\x80"), (b"\xA1\xA1", b"\xFE\xFE")],
+            "GBK-EUC" | "GBKp-EUC" | "ETen-B5" | "HKscs-B5" => &[(b"\x00", b"\x80"), (b"\x81\x40", b"\xFE\xFE")],
+            "GBK2K" => &[(b"\x00", b"\x7F"), (b"\x81\x40", b"\xFE\xFE"), (b"\x81\x30\x81\x30", b"\xFE\x39\xFE\x39")],
+            "90ms-RKSJ" | "90msp-RKSJ" => &[(b"\x00", b"\x80"), (b"\xA0", b"\xDF"), (b"\x81\x40", b"\x9F\xFC"), (b"\xE0\x40", b"\xFC\xFC")],
+            "EUC" => &[(b"\x00", b"\x80"), (b"\x8E\xA0", b"\x8E\xDF"), (b"\xA1\xA1", b"\xFE\xFE")],
+            "KSCms-UHC" | "KSCms-UHC-HW" => &[(b"\x00", b"\x80"), (b"\x81\x41", b"\xFE\xFE")],
+            _ => return None,
+        };
+        let codespace = codespace.iter().map(|&(lo, hi)| (lo.to_vec(), hi.to_vec())).collect();
+        Some(CodeMap { codespace, cids: vec![], vertical })
+    }
+
+    // split a string into codes, taking the shortest code that fits a codespace range.
+    // a byte that starts no code is taken on its own.
+    pub fn codes<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item=u32> + 'a {
+        let mut pos = 0;
+        std::iter::from_fn(move || {
+            if pos >= data.len() {
+                return None;
+            }
+            let rest = &data[pos..];
+            let len = self.codespace.iter()
+                .find(|(lo, hi)| rest.len() >= lo.len() && (0 .. lo.len()).all(|i| lo[i] <= rest[i] && rest[i] <= hi[i]))
+                .map_or(1, |(lo, _)| lo.len());
+            pos += len;
+            code(&rest[..len])
+        })
+    }
+
+    pub fn cid(&self, code: u32) -> Option<u32> {
+        // later mappings take precedence
+        self.cids.iter().rev()
+            .find(|&&(lo, hi, _)| lo <= code && code <= hi)
+            .and_then(|&(lo, _, cid)| cid.checked_add(code - lo))
+    }
+
+    // bytes taken by the ranges, for the size of the cache
+    pub fn size(&self) -> usize {
+        let codespace: usize = self.codespace.iter().map(|(lo, hi)| lo.len() + hi.len()).sum();
+        codespace + self.cids.len() * std::mem::size_of::<(u32, u32, u32)>()
+    }
+
+    // every mapped code of up to two bytes with its cid. the tables of a font are keyed by
+    // u16, and a range of longer codes could take forever to expand.
+    pub fn mappings(&self) -> impl Iterator<Item=(u32, u32)> + '_ {
+        self.cids.iter()
+            .filter(|&&(lo, _, _)| lo <= 0xFFFF)
+            .flat_map(|&(lo, hi, cid)| (lo ..= hi.min(0xFFFF)).map_while(move |code| Some((code, cid.checked_add(code - lo)?))))
+    }
+}
+
+fn code(bytes: &[u8]) -> Option<u32> {
+    if bytes.len() > 4 {
+        return None;
+    }
+    Some(bytes.iter().fold(0, |n, &b| n << 8 | b as u32))
+}
+
+fn tokens(data: &[u8]) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        match data[pos] {
+            b'%' => {
+                while pos < data.len() && !matches!(data[pos], b'\r' | b'\n') {
+                    pos += 1;
+                }
+            }
+            b'<' if data.get(pos + 1) == Some(&b'<') => pos += 2,
+            b'<' => {
+                let end = match data[pos..].iter().position(|&b| b == b'>') {
+                    Some(n) => pos + n,
+                    None => break,
+                };
+                let digits: Vec<u8> = data[pos + 1 .. end].iter()
+                    .filter_map(|&b| (b as char).to_digit(16))
+                    .map(|d| d as u8)
+                    .collect();
+                tokens.push(Token::Hex(digits.chunks(2).map(|c| c[0] << 4 | c.get(1).copied().unwrap_or(0)).collect()));
+                pos = end + 1;
+            }
+            b if b.is_ascii_whitespace() => pos += 1,
+            _ => {
+                let end = data[pos..].iter()
+                    .position(|&b| b.is_ascii_whitespace() || matches!(b, b'<' | b'>' | b'[' | b']' | b'(' | b')' | b'/' | b'%'))
+                    .map_or(data.len(), |n| pos + n);
+                let word = &data[pos .. end.max(pos + 1)];
+                match std::str::from_utf8(word).ok().and_then(|s| s.parse().ok()) {
+                    Some(n) => tokens.push(Token::Number(n)),
+                    None => tokens.push(Token::Word(word)),
+                }
+                pos = end.max(pos + 1);
+            }
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: &[u8] = b"
+        /CIDInit /ProcSet findresource begin
+        12 dict begin
+        begincmap
+        /WMode 1 def
+        1 begincodespacerange
+        <0000> <FFFF>
+        endcodespacerange
+        2 begincidrange
+        <0000> <00FF> 0
+        <0100> <01FF> 1000
+        endcidrange
+        1 begincidchar
+        <0120> 42
+        endcidchar
+        endcmap
+    ";
+
+    #[test]
+    fn parse() {
+        let map = CodeMap::parse(IDENTITY).unwrap();
+        assert!(map.vertical);
+        std::assert_eq!(map.cid(0x41), Some(0x41));
+        std::assert_eq!(map.cid(0x0105), Some(1005));
+        // the cidchar comes later and wins
+        std::assert_eq!(map.cid(0x0120), Some(42));
+        std::assert_eq!(map.cid(0x0200), None);
+    }
+
+    #[test]
+    fn parse_without_codespace() {
+        assert!(CodeMap::parse(b"begincmap 1 begincidrange <00> <FF> 0 endcidrange endcmap").is_none());
+    }
+
+    #[test]
+    fn codes() {
+        let map = CodeMap::parse(IDENTITY).unwrap();
+        std::assert_eq!(map.codes(b"\x00\x41\x01\x20").collect::<Vec<_>>(), [0x41, 0x0120]);
+        // a trailing byte is a code of its own
+        std::assert_eq!(map.codes(b"\x00\x41\x07").collect::<Vec<_>>(), [0x41, 0x07]);
+    }
+
+    #[test]
+    fn mixed_codespace() {
+        let map = CodeMap::parse(b"
+            2 begincodespacerange
+            <8140> <FEFE>
+            <00> <80>
+            endcodespacerange
+        ").unwrap();
+        std::assert_eq!(map.codes(b"A\xB0\xA1B").collect::<Vec<_>>(), [0x41, 0xB0A1, 0x42]);
+        // a lead byte without a valid second byte is taken on its own
+        std::assert_eq!(map.codes(b"\xB0\x20").collect::<Vec<_>>(), [0xB0, 0x20]);
+    }
+
+    #[test]
+    fn predefined() {
+        let gbk = CodeMap::predefined("GBK-EUC-H").unwrap();
+        assert!(!gbk.vertical);
+        std::assert_eq!(gbk.codes(b"1\xD6\xD02").collect::<Vec<_>>(), [0x31, 0xD6D0, 0x32]);
+        std::assert_eq!(gbk.cid(0x31), None);
+
+        // half width katakana are single bytes between the two byte ranges
+        let sjis = CodeMap::predefined("90ms-RKSJ-V").unwrap();
+        assert!(sjis.vertical);
+        std::assert_eq!(sjis.codes(b"\xB1\x82\xA0\xE0\x40").collect::<Vec<_>>(), [0xB1, 0x82A0, 0xE040]);
+
+        assert!(CodeMap::predefined("Identity-H").is_none());
+        assert!(CodeMap::predefined("UniGB-UCS2-H").is_none());
+    }
+
+    #[test]
+    fn mappings_stop_at_two_bytes() {
+        let map = CodeMap::parse(b"
+            1 begincodespacerange <00000000> <FFFFFFFF> endcodespacerange
+            2 begincidrange
+            <0000FFFE> <FFFFFFFF> 10
+            <00010000> <00020000> 0
+            endcidrange
+        ").unwrap();
+        std::assert_eq!(map.mappings().collect::<Vec<_>>(), [(0xFFFE, 10), (0xFFFF, 11)]);
+        std::assert_eq!(map.cid(0x0001_0005), Some(5));
+    }
+
+    #[test]
+    fn mappings_with_huge_cids() {
+        let map = CodeMap::parse(b"
+            1 begincodespacerange <0000> <FFFF> endcodespacerange
+            1 begincidrange <0000> <0003> 4294967294 endcidrange
+        ").unwrap();
+        std::assert_eq!(map.mappings().collect::<Vec<_>>(), [(0, u32::MAX - 1), (1, u32::MAX)]);
+        std::assert_eq!(map.cid(2), None);
+    }
+}
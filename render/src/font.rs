@@ -1,5 +1,5 @@
 use pdf::error::{PdfError, Result};
-use pdf::font::Font as PdfFont;
+use pdf::font::{Font as PdfFont, FontType};
 use pdf::object::*;
 use std::collections::HashMap;
 use std::ops::Deref;
@@ -9,20 +9,25 @@ use super::FontEntry;
 use font::{self};
 use globalcache::{sync::SyncCache, ValueSize};
 use std::hash::{Hash, Hasher};
+use istring::SmallString;
 use std::sync::Arc;
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+// the font and the length of the data it was parsed from
 #[derive(Clone)]
-pub struct FontRc(Arc<dyn font::Font + Send + Sync + 'static>);
+pub struct FontRc(Arc<dyn font::Font + Send + Sync + 'static>, usize);
 impl ValueSize for FontRc {
+    // the parsed font takes about as much memory as its file
     #[inline]
     fn size(&self) -> usize {
-        1 // TODO
+        self.1
     }
 }
-impl From<Box<dyn font::Font + Send + Sync + 'static>> for FontRc {
+impl FontRc {
     #[inline]
-    fn from(f: Box<dyn font::Font + Send + Sync + 'static>) -> Self {
-        FontRc(f.into())
+    fn new(f: Box<dyn font::Font + Send + Sync + 'static>, data_len: usize) -> Self {
+        FontRc(f.into(), data_len)
     }
 }
 impl Deref for FontRc {
@@ -45,58 +50,111 @@ impl Hash for FontRc {
         Arc::as_ptr(&self.0).hash(state)
     }
 }
+// Fonts to use for those that aren't embedded. Clones share the fonts parsed so far, also
+// between threads, so one can serve several files and caches.
+#[derive(Clone)]
 pub struct StandardCache {
     inner: Arc<SyncCache<String, Option<FontRc>>>,
     dir: PathBuf,
     fonts: HashMap<String, String>,
+    // substitute for fonts that aren't in the mapping
+    fallback: Option<String>,
+    // how many font files were read, by all clones
+    #[cfg(test)]
+    loads: Arc<AtomicUsize>,
 }
 impl StandardCache {
+    // learns the mapping from the fonts.json in dir, if there is one
     pub fn new(dir: PathBuf) -> Self {
-        if let Ok(data) = std::fs::read_to_string(dir.join("fonts.json")) {
-            let fonts: HashMap<String, String> =
-                serde_json::from_str(&data).expect("fonts.json is invalid");
-
-            StandardCache {
-                inner: SyncCache::new(),
-                dir,
-                fonts,
-            }
-        } else {
-            StandardCache {
-                inner: SyncCache::new(),
-                dir,
-                fonts: HashMap::new(),
-            }
+        let fonts = match std::fs::read_to_string(dir.join("fonts.json")) {
+            Ok(data) => serde_json::from_str(&data).expect("fonts.json is invalid"),
+            Err(_) => HashMap::new(),
+        };
+        StandardCache::with_mapping(dir, fonts)
+    }
+    // fonts maps font names to file names in dir
+    pub fn with_mapping(dir: PathBuf, fonts: HashMap<String, String>) -> Self {
+        StandardCache {
+            inner: SyncCache::new(),
+            dir,
+            fonts,
+            fallback: Some("Arial".into()),
+            #[cfg(test)]
+            loads: Arc::new(AtomicUsize::new(0)),
         }
     }
+    pub fn add_font(&mut self, name: impl Into<String>, file_name: impl Into<String>) {
+        self.fonts.insert(name.into(), file_name.into());
+    }
+    // the font to use in place of unknown ones, or None to leave them out
+    pub fn with_fallback(mut self, fallback: Option<String>) -> Self {
+        self.fallback = fallback;
+        self
+    }
+    fn file_name(&self, name: &str) -> Option<&String> {
+        if let Some(file_name) = self.fonts.get(name) {
+            return Some(file_name);
+        }
+        let fallback = self.fallback.as_ref()?;
+        debug!("using {} in place of {}", fallback, name);
+        self.fonts.get(fallback)
+    }
+}
+
+// Unicode for some codes of a font, which takes the place of what the font itself says.
+// For fonts with a wrong or missing /ToUnicode, where the right text is known.
+#[derive(Clone, Default)]
+pub struct FontOverrides {
+    // by font name, without the subset prefix
+    fonts: HashMap<String, HashMap<u16, SmallString>>,
+}
+impl FontOverrides {
+    pub fn new() -> Self {
+        FontOverrides::default()
+    }
+    // decode `code` of the font `name` as `text`
+    pub fn add(&mut self, name: &str, code: u16, text: &str) {
+        self.fonts.entry(base_name(name).into()).or_default().insert(code, text.into());
+    }
+    fn apply(&self, mut entry: FontEntry) -> FontEntry {
+        if let Some(codes) = self.fonts.get(base_name(&entry.name)) {
+            debug!("{} overridden codes for {}", codes.len(), entry.name);
+            entry.unicode_overrides = codes.clone();
+        }
+        entry
+    }
+}
+
+// subsets are named like ABCDEF+Times-Roman
+fn base_name(name: &str) -> &str {
+    match name.split_once('+') {
+        Some((tag, rest)) if tag.len() == 6 && tag.bytes().all(|b| b.is_ascii_uppercase()) => rest,
+        _ => name,
+    }
 }
 
 pub fn load_font(
     font_ref: &MaybeRef<PdfFont>,
     resolve: &impl Resolve,
     cache: &StandardCache,
+    overrides: &FontOverrides,
 ) -> Result<Option<FontEntry>> {
     let pdf_font = font_ref.clone();
     debug!("loading {:?}", pdf_font);
 
+    if let FontType::Type3 = pdf_font.subtype {
+        return Ok(Some(overrides.apply(FontEntry::type3(pdf_font, resolve)?)));
+    }
+
     let font: FontRc = match pdf_font.embedded_data(resolve) {
         Some(Ok(data)) => {
             let font = font::parse(&data).map_err(|e| {
-                let name = format!(
-                    "font_{}",
-                    pdf_font
-                        .name
-                        .as_ref()
-                        .map(|s| s.as_str())
-                        .unwrap_or("unnamed")
-                );
-                std::fs::write(&name, &data).unwrap();
-                println!("font dumped in {}", name);
+                warn!("failed to parse font {:?}: {:?}", pdf_font.name, e);
                 PdfError::Other {
                     msg: format!("Font Error: {:?}", e),
                 }
             })?;
-            FontRc::from(font)
+            FontRc::new(font, data.len())
         }
         Some(Err(e)) => return Err(e),
         None => {
@@ -104,9 +162,12 @@ pub fn load_font(
                 Some(ref name) => name.as_str(),
                 None => return Ok(None),
             };
-            match cache.fonts.get(name).or_else(|| cache.fonts.get("Arial")) {
+            match cache.file_name(name) {
                 Some(file_name) => {
                     let val = cache.inner.get(file_name.clone(), || {
+                        #[cfg(test)]
+                        cache.loads.fetch_add(1, Ordering::Relaxed);
+                        debug!("loading {} for {:?}", file_name, pdf_font.name);
                         let data = match std::fs::read(cache.dir.join(file_name)) {
                             Ok(data) => data,
                             Err(e) => {
@@ -115,7 +176,7 @@ pub fn load_font(
                             }
                         };
                         match font::parse(&data) {
-                            Ok(f) => Some(f.into()),
+                            Ok(f) => Some(FontRc::new(f, data.len())),
                             Err(e) => {
                                 warn!("Font Error: {:?}", e);
                                 return None;
@@ -137,5 +198,79 @@ pub fn load_font(
         }
     };
 
-    Ok(Some(FontEntry::build(font, pdf_font, resolve)?))
+    Ok(Some(overrides.apply(FontEntry::build(font, pdf_font, resolve)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testpdf;
+
+    fn files(dir: &str) -> Vec<std::path::PathBuf> {
+        let mut files: Vec<_> = std::fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn unparseable_font_leaves_no_file() {
+        let (file, font) = testpdf::font(&[
+            b"<< /Type /Font /Subtype /TrueType /BaseFont /Broken /FirstChar 32 /LastChar 32 /Widths [250] /FontDescriptor 5 0 R >>",
+            b"<< /Type /FontDescriptor /FontName /Broken /Flags 32 /FontBBox [0 0 1000 1000] /ItalicAngle 0 /Ascent 800 /Descent -200 /CapHeight 700 /FontFile2 6 0 R >>",
+            &testpdf::stream(b"not a font"),
+        ]);
+        let before = files(".");
+        let cache = StandardCache::with_mapping(PathBuf::from("."), HashMap::new());
+        assert!(load_font(&font, &file, &cache, &FontOverrides::new()).is_err());
+        std::assert_eq!(files("."), before);
+    }
+
+    #[test]
+    fn in_memory_mapping() {
+        let dir = std::env::temp_dir().join(format!("pdf_render_mapping_{}", std::process::id()));
+        let mapping = HashMap::from([("Arial".to_string(), "arial.ttf".to_string())]);
+        let mut cache = StandardCache::with_mapping(dir.clone(), mapping);
+        cache.add_font("Times-Roman", "times.ttf");
+        std::assert_eq!(cache.file_name("Times-Roman").map(String::as_str), Some("times.ttf"));
+        // unknown fonts fall back to Arial, unless there is no fallback
+        std::assert_eq!(cache.file_name("Courier").map(String::as_str), Some("arial.ttf"));
+        let cache = cache.with_fallback(None);
+        std::assert_eq!(cache.file_name("Courier"), None);
+
+        // the font is read from the file it was added with
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("times.ttf"), testpdf::truetype()).unwrap();
+        let (file, font) = testpdf::font(&[b"<< /Type /Font /Subtype /Type1 /BaseFont /Times-Roman >>"]);
+        let entry = load_font(&font, &file, &cache, &FontOverrides::new()).unwrap();
+        std::assert_eq!(entry.map(|entry| entry.name.clone()).as_deref(), Some("Times-Roman"));
+        let (file, font) = testpdf::font(&[b"<< /Type /Font /Subtype /Type1 /BaseFont /Courier >>"]);
+        assert!(load_font(&font, &file, &cache, &FontOverrides::new()).unwrap().is_none());
+        std::assert_eq!(cache.loads.load(Ordering::Relaxed), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn standard_font_is_loaded_once() {
+        let dir = std::env::temp_dir().join(format!("pdf_render_standard_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Helvetica.ttf"), testpdf::truetype()).unwrap();
+        let mapping = HashMap::from([("Helvetica".to_string(), "Helvetica.ttf".to_string())]);
+        let cache = StandardCache::with_mapping(dir.clone(), mapping);
+
+        // a document of its own on every thread, like pdf2text does for a portfolio
+        std::thread::scope(|scope| {
+            for _ in 0 .. 4 {
+                let cache = cache.clone();
+                scope.spawn(move || {
+                    let (file, font) = testpdf::font(&[b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>"]);
+                    assert!(load_font(&font, &file, &cache, &FontOverrides::new()).unwrap().is_some());
+                });
+            }
+        });
+        let (file, font) = testpdf::font(&[b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>"]);
+        assert!(load_font(&font, &file, &cache, &FontOverrides::new()).unwrap().is_some());
+
+        std::assert_eq!(cache.loads.load(Ordering::Relaxed), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
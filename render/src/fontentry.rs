@@ -1,12 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, hash_map::Entry};
 use font::{self, GlyphId, TrueTypeFont, CffFont, Type1Font, OpenTypeFont};
 use pdf::encoding::BaseEncoding;
-use pdf::font::{Font as PdfFont, Widths, CidToGidMap};
-use pdf::object::{Resolve, MaybeRef};
+use pdf::font::{Font as PdfFont, FontData, FontDescriptor, Widths, CidToGidMap};
+use pdf::object::{Resolve, MaybeRef, Object, Stream};
+use pdf::primitive::{Dictionary, Primitive};
+use pathfinder_geometry::transform2d::Transform2F;
 use pdf::error::PdfError;
 use pdf_encoding::{Encoding, glyphname_to_unicode};
 use istring::SmallString;
 use crate::font::FontRc;
+use crate::cmap::CodeMap;
 
 #[derive(Debug)]
 pub enum TextEncoding {
@@ -14,13 +17,48 @@ pub enum TextEncoding {
     Cmap(HashMap<u16, (GlyphId, Option<SmallString>)>)
 }
 
+// where the unicode of a font's text comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeSource {
+    // the /ToUnicode CMap of the pdf font
+    ToUnicode,
+    // a predefined unicode CMap as the /Encoding of a composite font, like UniGB-UCS2-H
+    PredefinedCMap,
+    // a standard encoding (and /Differences) of a simple font
+    BaseEncoding,
+    // the cmap or built-in encoding of the font program
+    EmbeddedCmap,
+    // codes are assumed to be unicode codepoints
+    RawGuess,
+}
+
 pub struct FontEntry {
-    pub font: FontRc,
+    // the font program, Type3 fonts have none as their glyphs are content streams
+    pub font: Option<FontRc>,
     pub pdf_font: MaybeRef<PdfFont>,
     pub encoding: TextEncoding,
     pub widths: Option<Widths>,
+    // from glyph space to text space
+    pub font_matrix: Transform2F,
+    // the advances of the codes of a Type3 font in glyph space, from /Widths or the d0 or d1
+    // that starts the procedure of their glyph
+    type3_widths: Option<HashMap<usize, f32>>,
+    // about how many entries widths has, for the size of the entry
+    width_count: usize,
     pub is_cid: bool,
     pub name: String,
+    pub decode_source: DecodeSource,
+    // advance of the space glyph in em, if the font has one
+    pub space_width: Option<f32>,
+    // in em, from the font descriptor (1 and 0 if there is none)
+    pub ascent: f32,
+    pub descent: f32,
+    // from the CMap /Encoding: how to split strings into codes, and their cids if it is embedded
+    pub code_map: Option<CodeMap>,
+    // writing mode 1, glyphs advance downwards and lines go from right to left
+    pub vertical: bool,
+    // unicode given by the user for some codes, in place of the decoded one
+    pub unicode_overrides: HashMap<u16, SmallString>,
 }
 impl FontEntry {
     pub fn build(font: FontRc, pdf_font: MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<FontEntry, PdfError> {
@@ -30,6 +68,8 @@ impl FontEntry {
         
         let to_unicode = t!(pdf_font.to_unicode(resolve).transpose());
         let mut font_codepoints = None;
+        // whether the font program itself says what its glyphs are
+        let mut knows_unicode = true;
         let glyph_unicode: HashMap<GlyphId, SmallString> = 
         if let Some(type1) = font.downcast_ref::<Type1Font>() {
             debug!("Font is Type1");
@@ -41,6 +81,7 @@ impl FontEntry {
         } else if let Some(cff) = font.downcast_ref::<CffFont>() {
             cff.unicode_map.iter().map(|(&u, &gid)| (GlyphId(gid as u32), u.into())).collect()
         } else {
+            knows_unicode = false;
             (0..font.num_glyphs())
                 .filter_map(|gid| std::char::from_u32(gid).map(|c| (GlyphId(gid), c.into())))
                 .collect()
@@ -77,6 +118,16 @@ impl FontEntry {
             }
         };
         
+        // Identity encoded text without a /ToUnicode can still be read through the unicode
+        // of the glyphs, if the codes can be traced back to them.
+        let glyph_map = |cid_for_gid: &dyn Fn(GlyphId) -> Option<u16>| {
+            if !knows_unicode {
+                return None;
+            }
+            glyph_cmap(&glyph_unicode, cid_for_gid)
+        };
+
+        let mut cmap_source = DecodeSource::RawGuess;
         let encoding = if let Some(map) = pdf_font.cid_to_gid_map() {
             is_cid = true;
             debug!("gid to cid map: {:?}", map);
@@ -87,11 +138,18 @@ impl FontEntry {
                             (cid, (Some(GlyphId(cid as u32)), s.into()))
                         }).collect();
                         TextEncoding::CID(Some(map))
+                    } else if let Some(cmap) = glyph_map(&|gid| u16::try_from(gid.0).ok()) {
+                        cmap_source = DecodeSource::EmbeddedCmap;
+                        cmap
                     } else {
                         TextEncoding::CID(None)
                     }
                 }
                 CidToGidMap::Table(ref data) => {
+                    cmap_source = match to_unicode {
+                        Some(_) => DecodeSource::ToUnicode,
+                        None => DecodeSource::EmbeddedCmap
+                    };
                     let cmap = data.iter().enumerate().map(|(cid, &gid)| {
                         let unicode = match to_unicode {
                             Some(ref u) => u.get(cid as u16).map(|s| s.into()),
@@ -102,9 +160,28 @@ impl FontEntry {
                     TextEncoding::Cmap(cmap)
                 }
             }
-        } else if base_encoding == Some(&BaseEncoding::IdentityH) {
+        } else if base_encoding == Some(&BaseEncoding::IdentityH) || is_identity_v(base_encoding) {
             is_cid = true;
-            TextEncoding::CID(build_map())
+            match build_map() {
+                Some(map) => TextEncoding::CID(Some(map)),
+                None => {
+                    // CFF based CID fonts map cids to glyphs through their charset,
+                    // for all others the cid is the glyph id
+                    let glyph_cid: Option<HashMap<u32, u16>> = font.downcast_ref::<CffFont>()
+                        .map(|cff| cff.sid_map.iter().map(|(&cid, &gid)| (gid as u32, cid as u16)).collect());
+                    let cmap = match glyph_cid {
+                        Some(ref glyph_cid) => glyph_map(&|gid| glyph_cid.get(&gid.0).copied()),
+                        None => glyph_map(&|gid| u16::try_from(gid.0).ok()),
+                    };
+                    match cmap {
+                        Some(cmap) => {
+                            cmap_source = DecodeSource::EmbeddedCmap;
+                            cmap
+                        }
+                        None => TextEncoding::CID(None),
+                    }
+                }
+            }
         } else {
             let mut cmap = HashMap::<u16, (GlyphId, Option<SmallString>)>::new();
             let source_encoding = match base_encoding {
@@ -124,6 +201,7 @@ impl FontEntry {
 
             match (source_encoding, font_encoding) {
                 (Some(source), Some(dest)) => {
+                    cmap_source = DecodeSource::BaseEncoding;
                     if let Some(transcoder) = source.to(dest) {
                         let forward = source.forward_map().unwrap();
                         for b in 0 .. 256 {
@@ -135,6 +213,7 @@ impl FontEntry {
                     }
                 },
                 (Some(enc), None) => {
+                    cmap_source = DecodeSource::BaseEncoding;
                     if let Some(encoder) = enc.to(Encoding::Unicode) {
                         for b in 0 .. 256 {
                             let unicode = encoder.translate(b as u32);
@@ -147,6 +226,7 @@ impl FontEntry {
                 }
                 _ => {
                     if let Some(cff) = font.downcast_ref::<CffFont>() {
+                        cmap_source = DecodeSource::EmbeddedCmap;
                         for (cp, &gid) in cff.codepoint_map.iter().enumerate() {
                             let gid = GlyphId(gid as u32);
                             let unicode = glyph_unicode.get(&gid).cloned();
@@ -161,15 +241,18 @@ impl FontEntry {
                 }
             }
             if let Some(encoding) = encoding {
+                if !encoding.differences.is_empty() {
+                    cmap_source = DecodeSource::BaseEncoding;
+                }
                 for (&cp, name) in encoding.differences.iter() {
-                    let uni = glyphname_to_unicode(name);
+                    let uni = glyph_name_unicode(name);
                     let gid = font.gid_for_name(&name).or_else(||
-                        uni.and_then(|s| s.chars().next()).and_then(|cp| font.gid_for_unicode_codepoint(cp as u32))
+                        uni.as_ref().and_then(|s| s.chars().next()).and_then(|cp| font.gid_for_unicode_codepoint(cp as u32))
                     ).or_else(||
                         font.gid_for_codepoint(cp)
                     ).unwrap_or(GlyphId(cp));
                     
-                    let unicode = uni.map(|s| s.into())
+                    let unicode = uni.as_deref().map(SmallString::from)
                         .or_else(|| std::char::from_u32(0xf000 + gid.0).map(SmallString::from));
                     
                     debug!("{} -> gid {:?}, unicode {:?}", cp, gid, unicode);
@@ -178,12 +261,14 @@ impl FontEntry {
             } else {
                 if let Some(ref u) = to_unicode {
                     debug!("using to_unicode to build cmap");
+                    cmap_source = DecodeSource::ToUnicode;
                     for (cp, unicode) in u.iter() {
                         if let Some(gid) = font.gid_for_unicode_codepoint(cp as u32) {
                             cmap.insert(cp as u16, (gid, Some(unicode.into())));
                         }
                     }
                 } else if let Some(codepoints) = font_codepoints {
+                    cmap_source = DecodeSource::EmbeddedCmap;
                     for (&cp, &gid) in codepoints.iter() {
                         cmap.insert(cp as u16, (GlyphId(gid), glyph_unicode.get(&GlyphId(gid)).cloned()));
                     }
@@ -206,21 +291,564 @@ impl FontEntry {
             }
         };
         
+        // an embedded CMap maps the codes to cids first, which then select the glyphs
+        let code_map = if is_cid { embedded_code_map(&pdf_font, resolve) } else { None };
+        let encoding = match code_map {
+            Some(ref code_map) => {
+                cmap_source = match to_unicode {
+                    Some(_) => DecodeSource::ToUnicode,
+                    None => DecodeSource::EmbeddedCmap,
+                };
+                let cid_to_gid = pdf_font.cid_to_gid_map();
+                let cff = font.downcast_ref::<CffFont>();
+                let cmap = code_map.mappings().filter_map(|(code, cid)| {
+                    let code = u16::try_from(code).ok()?;
+                    let gid = match (cid_to_gid, cff) {
+                        (Some(CidToGidMap::Table(data)), _) => GlyphId(*data.get(cid as usize)? as u32),
+                        (_, Some(cff)) => GlyphId(*cff.sid_map.get(&(cid as u16))? as u32),
+                        _ => GlyphId(cid),
+                    };
+                    let unicode = match to_unicode {
+                        Some(ref u) => u.get(code).map(|s| s.into()),
+                        None => glyph_unicode.get(&gid).cloned(),
+                    };
+                    Some((code, (gid, unicode)))
+                }).collect();
+                TextEncoding::Cmap(cmap)
+            }
+            None => encoding,
+        };
+
+        let cmap_name = match base_encoding {
+            Some(BaseEncoding::Other(name)) => Some(&**name),
+            _ => None,
+        };
+        let decode_source = decode_source(&encoding, cmap_source, cmap_name);
+        debug!("decode source: {:?}", decode_source);
+
+        // a predefined CMap still tells how long its codes are
+        let code_map = match (code_map, cmap_name) {
+            (None, Some(name)) if is_cid => CodeMap::predefined(name),
+            (code_map, _) => code_map,
+        };
+
         let widths = pdf_font.widths(resolve)?;
+        let width_count = if widths.is_some() { width_count(&pdf_font, resolve) } else { 0 };
+        let space_width = space_code(&encoding).and_then(|(code, gid)| {
+            let cid = code_map.as_ref().and_then(|m| m.cid(code as u32)).unwrap_or(code as u32);
+            widths.as_ref().map(|w| w.get(cid as usize) * 0.001)
+                .or_else(|| gid.and_then(|gid| font.glyph(gid)).map(|g| font.font_matrix().m11() * g.metrics.advance))
+        });
+        debug!("space width: {:?}", space_width);
+
+        // predefined CMaps for vertical writing end in -V, like Identity-V and UniJIS-UCS2-V
+        let vertical = match base_encoding {
+            Some(BaseEncoding::Other(name)) => name.ends_with("-V"),
+            _ => code_map.as_ref().is_some_and(|m| m.vertical),
+        };
+        debug!("vertical: {}", vertical);
+
+        let (ascent, descent) = vertical_metrics(&pdf_font, resolve).unwrap_or((1.0, 0.0));
+        debug!("ascent {}, descent {}", ascent, descent);
+
         let name = pdf_font.name.as_ref().ok_or_else(|| PdfError::Other { msg: "font has no name".into() })?.as_str().into();
         Ok(FontEntry {
-            font,
+            font_matrix: font.font_matrix(),
+            font: Some(font),
             pdf_font,
             encoding,
             is_cid,
             widths,
+            type3_widths: None,
+            width_count,
             name,
+            decode_source,
+            space_width,
+            ascent,
+            descent,
+            code_map,
+            vertical,
+            unicode_overrides: HashMap::new(),
         })
     }
+
+    // A Type3 font draws its glyphs with content streams, which aren't run. The text comes
+    // from the /ToUnicode or the glyph names of the /Encoding, the advances from the /Widths
+    // through the /FontMatrix.
+    pub fn type3(pdf_font: MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<FontEntry, PdfError> {
+        let dict = match pdf_font.data {
+            FontData::Other(ref dict) => dict,
+            _ => return Err(PdfError::Other { msg: "not a Type3 font".into() }),
+        };
+        let font_matrix = match numbers(dict.get("FontMatrix"), resolve) {
+            Some(m) if m.len() == 6 => Transform2F::row_major(m[0], m[2], m[4], m[1], m[3], m[5]),
+            _ => Transform2F::from_scale(0.001),
+        };
+        let first_char = match dict.get("FirstChar") {
+            Some(&Primitive::Integer(n)) => n.max(0) as usize,
+            _ => 0,
+        };
+        let mut type3_widths: HashMap<usize, f32> = numbers(dict.get("Widths"), resolve).unwrap_or_default()
+            .into_iter().enumerate()
+            .map(|(i, w)| (first_char + i, w))
+            .collect();
+
+        let to_unicode = t!(pdf_font.to_unicode(resolve).transpose());
+        let mut decode_source = DecodeSource::RawGuess;
+        // there are no glyph ids, the code stands in for them
+        let mut cmap = HashMap::<u16, (GlyphId, Option<SmallString>)>::new();
+        let char_procs = dictionary(dict.get("CharProcs"), resolve);
+        if let Some(encoding) = pdf_font.encoding() {
+            for (&code, name) in encoding.differences.iter() {
+                if let Entry::Vacant(entry) = type3_widths.entry(code as usize) {
+                    let width = char_procs.as_ref().and_then(|procs| procs.get(name.as_str())).and_then(|p| glyph_proc_width(p, resolve));
+                    if let Some(width) = width {
+                        entry.insert(width);
+                    }
+                }
+                if let Some(unicode) = glyph_name_unicode(name) {
+                    decode_source = DecodeSource::BaseEncoding;
+                    cmap.insert(code as u16, (GlyphId(code), Some(unicode.as_str().into())));
+                }
+            }
+        }
+        if let Some(ref to_unicode) = to_unicode {
+            decode_source = DecodeSource::ToUnicode;
+            for (code, unicode) in to_unicode.iter() {
+                cmap.insert(code, (GlyphId(code as u32), Some(unicode.into())));
+            }
+        }
+        debug!("type3 decode source: {:?}", decode_source);
+
+        let mut entry = FontEntry {
+            font: None,
+            pdf_font: pdf_font.clone(),
+            encoding: TextEncoding::Cmap(cmap),
+            widths: None,
+            font_matrix,
+            type3_widths: Some(type3_widths),
+            width_count: 0,
+            is_cid: false,
+            name: match dict.get("Name") {
+                Some(Primitive::Name(name)) => name.as_str().into(),
+                _ => "Type3".into(),
+            },
+            decode_source,
+            space_width: None,
+            ascent: 1.0,
+            descent: 0.0,
+            code_map: None,
+            vertical: false,
+            unicode_overrides: HashMap::new(),
+        };
+        entry.space_width = space_code(&entry.encoding).and_then(|(code, _)| entry.advance_width(code as usize));
+        Ok(entry)
+    }
+
+    // the advance of a code (or a cid) in em, as the font dictionary gives it
+    pub fn advance_width(&self, index: usize) -> Option<f32> {
+        match self.type3_widths {
+            Some(ref widths) => widths.get(&index).map(|w| w * self.font_matrix.m11()),
+            None => self.widths.as_ref().map(|w| w.get(index) * 0.001),
+        }
+    }
+}
+
+// an array of numbers, which may be behind a reference
+fn numbers(p: Option<&Primitive>, resolve: &impl Resolve) -> Option<Vec<f32>> {
+    let array = match *p? {
+        Primitive::Array(ref a) => a.clone(),
+        Primitive::Reference(r) => match resolve.resolve(r).ok()? {
+            Primitive::Array(a) => a,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    array.iter().map(|p| match *p {
+        Primitive::Integer(n) => Some(n as f32),
+        Primitive::Number(n) => Some(n),
+        _ => None,
+    }).collect()
+}
+
+// a dictionary, which may be behind a reference
+fn dictionary(p: Option<&Primitive>, resolve: &impl Resolve) -> Option<Dictionary> {
+    match *p? {
+        Primitive::Dictionary(ref dict) => Some(dict.clone()),
+        Primitive::Reference(r) => match resolve.resolve(r).ok()? {
+            Primitive::Dictionary(dict) => Some(dict),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The advance a Type3 glyph procedure sets with `wx wy d0` or `wx wy llx lly urx ury d1`
+// before it draws anything. The pdf crate skips both operators, so they are read here.
+fn glyph_proc_width(p: &Primitive, resolve: &impl Resolve) -> Option<f32> {
+    let stream = Stream::<()>::from_primitive(p.clone(), resolve).ok()?;
+    let data = stream.data(resolve).ok()?;
+    let mut operands: Vec<f32> = vec![];
+    for token in data.split(|b| b.is_ascii_whitespace()).filter(|t| !t.is_empty()) {
+        match token {
+            b"d0" if operands.len() >= 2 => return Some(operands[operands.len() - 2]),
+            b"d1" if operands.len() >= 6 => return Some(operands[operands.len() - 6]),
+            _ => match std::str::from_utf8(token).ok()?.parse() {
+                Ok(n) => operands.push(n),
+                // some other operator came first
+                Err(_) => return None,
+            },
+        }
+    }
+    None
+}
+
+// the cids of the glyphs, mapped to them and their unicode
+fn glyph_cmap(glyph_unicode: &HashMap<GlyphId, SmallString>, cid_for_gid: &dyn Fn(GlyphId) -> Option<u16>) -> Option<TextEncoding> {
+    let cmap: HashMap<u16, (GlyphId, Option<SmallString>)> = glyph_unicode.iter()
+        .filter_map(|(&gid, unicode)| Some((cid_for_gid(gid)?, (gid, Some(unicode.clone())))))
+        .collect();
+    (!cmap.is_empty()).then(|| TextEncoding::Cmap(cmap))
+}
+
+// Identity-V only differs from Identity-H in the writing mode
+fn is_identity_v(base_encoding: Option<&BaseEncoding>) -> bool {
+    matches!(base_encoding, Some(BaseEncoding::Other(name)) if name == "Identity-V")
+}
+
+// predefined CMaps whose codes are the unicode of the text, like UniGB-UCS2-H or UniJIS-UTF16-V
+fn is_unicode_cmap(name: &str) -> bool {
+    name.starts_with("Uni") && (name.contains("-UCS2-") || name.contains("-UTF16-"))
+}
+
+// which table the text of a font ends up decoded by, given the one that built its cmap
+// and the name of its /Encoding CMap
+fn decode_source(encoding: &TextEncoding, cmap_source: DecodeSource, cmap_name: Option<&str>) -> DecodeSource {
+    let source = match *encoding {
+        TextEncoding::CID(Some(_)) => DecodeSource::ToUnicode,
+        TextEncoding::CID(None) => DecodeSource::RawGuess,
+        TextEncoding::Cmap(_) => cmap_source,
+    };
+    match source {
+        // taking the codes for unicode is no guess with these
+        DecodeSource::RawGuess if cmap_name.is_some_and(is_unicode_cmap) => DecodeSource::PredefinedCMap,
+        source => source,
+    }
+}
+
+// the /Encoding of a composite font, if it is an embedded CMap stream
+fn embedded_code_map(pdf_font: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Option<CodeMap> {
+    let dict = font_dict(pdf_font, resolve)?;
+    let encoding = match *dict.get("Encoding")? {
+        Primitive::Reference(r) => resolve.resolve(r).ok()?,
+        _ => return None,
+    };
+    let stream = Stream::<()>::from_primitive(encoding, resolve).ok()?;
+    let data = stream.data(resolve).ok()?;
+    let code_map = CodeMap::parse(&data);
+    debug!("embedded cmap: {}", code_map.is_some());
+    code_map
+}
+
+// the dictionary of a font, for the entries pdf::font keeps to itself
+fn font_dict(pdf_font: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Option<Dictionary> {
+    match *pdf_font {
+        MaybeRef::Indirect(ref r) => dictionary(Some(&Primitive::Reference(r.get_ref().get_inner())), resolve),
+        MaybeRef::Direct(_) => None,
+    }
+}
+
+// the dictionary of the CIDFont of a composite font, or of the font itself if it is one
+fn cid_font_dict(pdf_font: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Option<Dictionary> {
+    let dict = font_dict(pdf_font, resolve)?;
+    match pdf_font.data {
+        FontData::Type0(_) => {
+            let fonts = dict.get("DescendantFonts")?.clone().resolve(resolve).ok()?.into_array().ok()?;
+            dictionary(fonts.first(), resolve)
+        }
+        FontData::CIDFontType0(_) | FontData::CIDFontType2(_) => Some(dict),
+        _ => None,
+    }
+}
+
+// ascent and descent in em, falling back to the font bbox if the descriptor doesn't give them
+fn vertical_metrics(pdf_font: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Option<(f32, f32)> {
+    let cid_descriptor;
+    let descriptor = match pdf_font.data {
+        FontData::Type1(ref f) | FontData::TrueType(ref f) => f.font_descriptor.as_ref()?,
+        _ => {
+            let dict = cid_font_dict(pdf_font, resolve)?;
+            cid_descriptor = FontDescriptor::from_primitive(dict.get("FontDescriptor")?.clone(), resolve).ok()?;
+            &cid_descriptor
+        }
+    };
+    let (ascent, descent) = match (descriptor.ascent, descriptor.descent) {
+        (Some(ascent), Some(descent)) if ascent != 0. || descent != 0. => (ascent, descent),
+        _ => (descriptor.font_bbox.top, descriptor.font_bbox.bottom),
+    };
+    if ascent <= descent {
+        return None;
+    }
+    Some((ascent * 0.001, descent * 0.001))
+}
+
+// Map a glyph name to unicode the way the Adobe Glyph List specification does: drop the
+// suffix after the first period, split ligatures at underscores, and look up each part in
+// the AGL or read it as uniXXXX (several code units) or uXXXX to uXXXXXX.
+fn glyph_name_unicode(name: &str) -> Option<String> {
+    if let Some(s) = glyphname_to_unicode(name) {
+        return Some(s.into());
+    }
+    let base = name.split('.').next().unwrap_or("");
+    let mut out = String::new();
+    for part in base.split('_') {
+        if let Some(s) = glyphname_to_unicode(part) {
+            out.push_str(s);
+        } else if let Some(hex) = part.strip_prefix("uni").filter(|h| h.len() >= 4 && h.len() % 4 == 0) {
+            for i in (0 .. hex.len()).step_by(4) {
+                out.push(scalar(&hex[i .. i + 4])?);
+            }
+        } else if let Some(hex) = part.strip_prefix('u').filter(|h| (4..=6).contains(&h.len())) {
+            out.push(scalar(hex)?);
+        } else {
+            return None;
+        }
+    }
+    if out.is_empty() {
+        None
+    } else {
+        Some(out)
+    }
+}
+
+// uppercase hex digits only, as the AGL specification demands
+fn scalar(hex: &str) -> Option<char> {
+    if !hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'A'..=b'F')) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok().and_then(std::char::from_u32)
+}
+
+// find the code (and glyph) that decodes to a space
+fn space_code(encoding: &TextEncoding) -> Option<(u16, Option<GlyphId>)> {
+    match *encoding {
+        TextEncoding::CID(None) => Some((0x20, Some(GlyphId(0x20)))),
+        TextEncoding::CID(Some(ref map)) => map.iter()
+            .filter(|(_, (_, unicode))| &**unicode == " ")
+            .map(|(&cid, &(gid, _))| (cid, gid))
+            .min_by_key(|&(cid, _)| cid),
+        TextEncoding::Cmap(ref map) => map.iter()
+            .filter(|(_, (_, unicode))| unicode.as_deref() == Some(" "))
+            .map(|(&cp, &(gid, _))| (cp, Some(gid)))
+            .min_by_key(|&(cp, _)| cp),
+    }
+}
+
+// about how many widths the font dictionary gives
+fn width_count(pdf_font: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> usize {
+    match pdf_font.data {
+        FontData::Type1(ref f) | FontData::TrueType(ref f) => f.widths.len(),
+        _ => cid_font_dict(pdf_font, resolve)
+            .and_then(|dict| dict.get("W")?.clone().resolve(resolve).ok()?.into_array().ok())
+            .map_or(0, |widths| widths.iter()
+                .map(|p| match *p {
+                    Primitive::Array(ref widths) => widths.len(),
+                    _ => 1,
+                })
+                .sum()
+            ),
+    }
+}
+
+fn table_size<K, V>(map: &HashMap<K, V>) -> usize {
+    map.len() * std::mem::size_of::<(K, V)>()
 }
 
 impl globalcache::ValueSize for FontEntry {
+    // the font program and the tables decoding and measuring its text
     fn size(&self) -> usize {
-        1 // TODO
+        let encoding = match self.encoding {
+            TextEncoding::CID(ref map) => map.as_ref().map_or(0, table_size),
+            TextEncoding::Cmap(ref map) => table_size(map),
+        };
+        let widths = match self.type3_widths {
+            Some(ref widths) => table_size(widths),
+            None => self.width_count * std::mem::size_of::<f32>(),
+        };
+        globalcache::ValueSize::size(&self.font)
+            + encoding
+            + widths
+            + self.code_map.as_ref().map_or(0, |code_map| code_map.size())
+            + table_size(&self.unicode_overrides)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testpdf;
+
+    #[test]
+    fn to_unicode_ranges() {
+        let [type3, to_unicode] = testpdf::abc_font_mapping(4, "2 beginbfrange <41> <43> <0061> <61> <62> [<00DF> <0066006C>] endbfrange");
+        let (file, font) = testpdf::font(&[
+            &type3,
+            &to_unicode,
+        ]);
+        let entry = FontEntry::type3(font, &file).unwrap();
+        std::assert_eq!(entry.decode_source, DecodeSource::ToUnicode);
+        let cmap = match entry.encoding {
+            TextEncoding::Cmap(ref cmap) => cmap,
+            _ => panic!("expected a cmap"),
+        };
+        let unicode = |code: u16| cmap.get(&code).and_then(|(_, unicode)| unicode.as_deref());
+        // the destination of a range counts up from its first code
+        std::assert_eq!(unicode(0x41), Some("a"));
+        std::assert_eq!(unicode(0x43), Some("c"));
+        // or each code of the range gets its own
+        std::assert_eq!(unicode(0x61), Some("ß"));
+        std::assert_eq!(unicode(0x62), Some("fl"));
+        std::assert_eq!(unicode(0x44), None);
+    }
+
+    #[test]
+    fn type3_advances() {
+        // a glyph space of 100 units to the em, /C has no width but its glyph has one
+        let (file, font) = testpdf::font(&[
+            b"<< /Type /Font /Subtype /Type3 /FontBBox [0 0 100 100] /FontMatrix [0.01 0 0 0.01 0 0] /CharProcs << /C 6 0 R >> /FirstChar 65 /LastChar 66 /Widths [50 100] /Encoding << /Differences [67 /C] >> /ToUnicode 5 0 R >>",
+            &testpdf::stream(b"
+                begincmap
+                1 begincodespacerange <00> <FF> endcodespacerange
+                1 beginbfrange <41> <42> <0041> endbfrange
+                endcmap
+            "),
+            &testpdf::stream(b"25 0 d0 0 0 m 25 0 l 25 100 l f"),
+        ]);
+        let entry = FontEntry::type3(font, &file).unwrap();
+        std::assert_eq!(entry.font_matrix, Transform2F::from_scale(0.01));
+        std::assert_eq!(entry.advance_width(0x41), Some(0.5));
+        std::assert_eq!(entry.advance_width(0x42), Some(1.0));
+        std::assert_eq!(entry.advance_width(0x43), Some(0.25));
+        std::assert_eq!(entry.advance_width(0x44), None);
+        // the ToUnicode and the glyph names of the encoding
+        let cmap = match entry.encoding {
+            TextEncoding::Cmap(ref cmap) => cmap,
+            _ => panic!("expected a cmap"),
+        };
+        let unicode = |code: u16| cmap.get(&code).and_then(|(_, unicode)| unicode.as_deref());
+        std::assert_eq!(unicode(0x41), Some("A"));
+        std::assert_eq!(unicode(0x42), Some("B"));
+        std::assert_eq!(unicode(0x43), Some("C"));
+    }
+
+    #[test]
+    fn glyph_names() {
+        let unicode = |name| glyph_name_unicode(name);
+        std::assert_eq!(unicode("A"), Some("A".into()));
+        std::assert_eq!(unicode("Lcommaaccent"), Some("\u{13B}".into()));
+        // variants and ligatures
+        std::assert_eq!(unicode("a.sc"), Some("a".into()));
+        std::assert_eq!(unicode("T_h"), Some("Th".into()));
+        std::assert_eq!(unicode("a_uni0301.alt"), Some("a\u{301}".into()));
+        // code points
+        std::assert_eq!(unicode("uni20AC"), Some("€".into()));
+        std::assert_eq!(unicode("uni00410042"), Some("AB".into()));
+        std::assert_eq!(unicode("u1F600"), Some("\u{1F600}".into()));
+        // lowercase hex, surrogates, the wrong number of digits and unknown names
+        std::assert_eq!(unicode("uni20ac"), None);
+        std::assert_eq!(unicode("uniD800"), None);
+        std::assert_eq!(unicode("uni004"), None);
+        std::assert_eq!(unicode("u12"), None);
+        std::assert_eq!(unicode("g123"), None);
+        std::assert_eq!(unicode(".notdef"), None);
+    }
+
+    #[test]
+    fn identity_codes_through_glyphs() {
+        let glyph_unicode: HashMap<GlyphId, SmallString> = [(1, "\u{4E2D}"), (2, "\u{6587}"), (70000, "x")].iter()
+            .map(|&(gid, s)| (GlyphId(gid), s.into()))
+            .collect();
+        let unicode = |encoding: &TextEncoding, cid: u16| match encoding {
+            TextEncoding::Cmap(cmap) => cmap.get(&cid).map(|(gid, s)| (gid.0, s.as_deref().unwrap().to_owned())),
+            _ => panic!("expected a cmap"),
+        };
+
+        // the cid is the glyph id, as far as it fits
+        let identity = glyph_cmap(&glyph_unicode, &|gid| u16::try_from(gid.0).ok()).unwrap();
+        std::assert_eq!(unicode(&identity, 1), Some((1, "\u{4E2D}".into())));
+        std::assert_eq!(unicode(&identity, 2), Some((2, "\u{6587}".into())));
+        std::assert_eq!(unicode(&identity, 3), None);
+        std::assert_eq!(unicode(&identity, 70000u32 as u16), None);
+
+        // or a charset maps the glyphs to their cids
+        let charset: HashMap<u32, u16> = [(1, 1200), (2, 1201)].into_iter().collect();
+        let cff = glyph_cmap(&glyph_unicode, &|gid| charset.get(&gid.0).copied()).unwrap();
+        std::assert_eq!(unicode(&cff, 1200), Some((1, "\u{4E2D}".into())));
+        std::assert_eq!(unicode(&cff, 1201), Some((2, "\u{6587}".into())));
+        std::assert_eq!(unicode(&cff, 1), None);
+
+        // nothing to map to
+        assert!(glyph_cmap(&glyph_unicode, &|_| None).is_none());
+    }
+
+    #[test]
+    fn ascent_and_descent() {
+        let (file, font) = testpdf::font(&[
+            b"<< /Type /Font /Subtype /TrueType /BaseFont /A /FirstChar 32 /LastChar 32 /Widths [250] /FontDescriptor 5 0 R >>",
+            b"<< /Type /FontDescriptor /FontName /A /Flags 32 /FontBBox [0 -250 1000 750] /ItalicAngle 0 /Ascent 800 /Descent -200 >>",
+        ]);
+        std::assert_eq!(vertical_metrics(&font, &file), Some((0.8, -0.2)));
+
+        // without /Ascent and /Descent the bbox gives them
+        let (file, font) = testpdf::font(&[
+            b"<< /Type /Font /Subtype /TrueType /BaseFont /A /FirstChar 32 /LastChar 32 /Widths [250] /FontDescriptor 5 0 R >>",
+            b"<< /Type /FontDescriptor /FontName /A /Flags 32 /FontBBox [0 -200 1000 800] /ItalicAngle 0 >>",
+        ]);
+        std::assert_eq!(vertical_metrics(&font, &file), Some((0.8, -0.2)));
+
+        // a composite font has them in the descriptor of its CIDFont
+        let (file, font) = testpdf::font(&[
+            b"<< /Type /Font /Subtype /Type0 /BaseFont /A /Encoding /Identity-H /DescendantFonts [5 0 R] >>",
+            b"<< /Type /Font /Subtype /CIDFontType2 /BaseFont /A /CIDSystemInfo << /Registry (Adobe) /Ordering (Identity) /Supplement 0 >> /FontDescriptor 6 0 R >>",
+            b"<< /Type /FontDescriptor /FontName /A /Flags 4 /FontBBox [0 -120 1000 880] /ItalicAngle 0 /Ascent 800 /Descent -200 >>",
+        ]);
+        std::assert_eq!(vertical_metrics(&font, &file), Some((0.8, -0.2)));
+    }
+
+    #[test]
+    fn size_grows_with_the_font() {
+        use globalcache::ValueSize;
+
+        let widths = |n: usize| vec!["500"; n].join(" ");
+        let type3 = |n: usize| format!("<< /Type /Font /Subtype /Type3 /FontBBox [0 0 1000 1000] /FontMatrix [0.001 0 0 0.001 0 0] /CharProcs << >> /FirstChar 0 /LastChar {} /Widths [{}] >>", n - 1, widths(n));
+        let (file, font) = testpdf::font(&[type3(3).as_bytes()]);
+        let small = FontEntry::type3(font, &file).unwrap();
+        let (file, font) = testpdf::font(&[type3(256).as_bytes()]);
+        let large = FontEntry::type3(font, &file).unwrap();
+        assert!(large.size() > small.size());
+
+        // the widths of a composite font are in its CIDFont
+        let (file, font) = testpdf::font(&[
+            b"<< /Type /Font /Subtype /Type0 /BaseFont /A /Encoding /Identity-H /DescendantFonts [5 0 R] >>",
+            format!("<< /Type /Font /Subtype /CIDFontType2 /BaseFont /A /CIDSystemInfo << /Registry (Adobe) /Ordering (Identity) /Supplement 0 >> /FontDescriptor 6 0 R /W [1 [{}]] >>", widths(100)).as_bytes(),
+            b"<< /Type /FontDescriptor /FontName /A /Flags 4 /FontBBox [0 -120 1000 880] /ItalicAngle 0 >>",
+        ]);
+        assert!(width_count(&font, &file) >= 100);
+    }
+
+    #[test]
+    fn decode_source_of_predefined_cmaps() {
+        let raw = TextEncoding::CID(None);
+        std::assert_eq!(decode_source(&raw, DecodeSource::RawGuess, Some("UniGB-UCS2-H")), DecodeSource::PredefinedCMap);
+        std::assert_eq!(decode_source(&raw, DecodeSource::RawGuess, Some("UniJIS-UTF16-V")), DecodeSource::PredefinedCMap);
+        // the codes of other CMaps aren't unicode
+        std::assert_eq!(decode_source(&raw, DecodeSource::RawGuess, Some("GBK-EUC-H")), DecodeSource::RawGuess);
+        std::assert_eq!(decode_source(&raw, DecodeSource::RawGuess, None), DecodeSource::RawGuess);
+
+        let cmap = TextEncoding::Cmap(HashMap::new());
+        std::assert_eq!(decode_source(&cmap, DecodeSource::RawGuess, Some("UniKS-UCS2-H")), DecodeSource::PredefinedCMap);
+        // a /ToUnicode still takes precedence
+        std::assert_eq!(decode_source(&cmap, DecodeSource::ToUnicode, Some("UniKS-UCS2-H")), DecodeSource::ToUnicode);
+        std::assert_eq!(decode_source(&TextEncoding::CID(Some(HashMap::new())), DecodeSource::RawGuess, Some("UniGB-UCS2-H")), DecodeSource::ToUnicode);
     }
 }
@@ -1,6 +1,7 @@
 #[macro_use] extern crate log;
 #[macro_use] extern crate pdf;
 
+// returns an error instead of panicking, so the tests use std::assert_eq!
 macro_rules! assert_eq {
     ($a:expr, $b:expr) => {
         if $a != $b {
@@ -26,16 +27,27 @@ pub mod tracer;
 mod image;
 mod scene;
 mod font;
+mod cmap;
+#[cfg(feature = "salvage")]
+mod salvage;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testpdf;
 
 pub use cache::{Cache};
-pub use fontentry::{FontEntry, TextEncoding};
-pub use backend::{DrawMode, Backend};
+pub use crate::font::{FontOverrides, StandardCache};
+pub use fontentry::{FontEntry, TextEncoding, DecodeSource};
+pub use backend::{DrawMode, Backend, MissingFont, Undecoded, TjSpacing};
 pub use scene::SceneBackend;
+pub use renderstate::text_string;
 pub use crate::image::{load_image, ImageData};
+#[cfg(feature = "salvage")]
+pub use salvage::salvage_page;
 use custom_debug_derive::Debug;
 
 use pdf::object::*;
 use pdf::error::PdfError;
+use pdf::primitive::Primitive;
+use pdf::content::{parse_ops, Content, Op, TextMode};
 use pathfinder_geometry::{
     vector::{Vector2F},
     rect::RectF, transform2d::Transform2F,
@@ -78,9 +90,45 @@ pub fn page_bounds(page: &Page) -> RectF {
     let Rect { left, right, top, bottom } = page.media_box().expect("no media box");
     RectF::from_points(Vector2F::new(left, bottom), Vector2F::new(right, top)) * SCALE
 }
-pub fn render_page(backend: &mut impl Backend, resolve: &impl Resolve, page: &Page, transform: Transform2F) -> Result<Transform2F, PdfError> {
+// The /Rotate of the page in degrees, inherited from the page tree. pdf reads a page without
+// the entry as /Rotate 0 and ignores the nodes above it, so they and the page itself are read
+// as plain dictionaries.
+pub fn page_rotation(resolve: &impl Resolve, page: &PageRc) -> i32 {
+    let mut node = page.get_ref().get_inner();
+    // page trees are shallow in practice, this only guards against cycles
+    for _ in 0 .. 32 {
+        let dict = match resolve.resolve(node) {
+            Ok(Primitive::Dictionary(dict)) => dict,
+            _ => break,
+        };
+        if let Some(rotate) = dict.get("Rotate") {
+            let rotate = match *rotate {
+                Primitive::Reference(r) => resolve.resolve(r).ok(),
+                ref p => Some(p.clone()),
+            };
+            return rotate.and_then(|p| p.as_integer().ok()).unwrap_or(0);
+        }
+        match dict.get("Parent") {
+            Some(&Primitive::Reference(parent)) => node = parent,
+            _ => break,
+        }
+    }
+    0
+}
+// Width and height of the page as shown, in points: the crop box (inherited or falling back
+// to the media box), with the two swapped when the page is turned a quarter.
+pub fn page_dimensions(resolve: &impl Resolve, page: &PageRc) -> Result<(f32, f32), PdfError> {
+    let Rect { left, right, top, bottom } = page.crop_box()?;
+    let (width, height) = ((right - left).abs(), (top - bottom).abs());
+    match page_rotation(resolve, page).rem_euclid(180) {
+        90 => Ok((height, width)),
+        _ => Ok((width, height)),
+    }
+}
+// set the view box of the backend and return the transformation from page space
+pub(crate) fn page_transform(backend: &mut impl Backend, resolve: &impl Resolve, page: &PageRc, transform: Transform2F) -> Transform2F {
     let bounds = page_bounds(page);
-    let rotate = Transform2F::from_rotation(page.rotate as f32 * std::f32::consts::PI / 180.);
+    let rotate = Transform2F::from_rotation(page_rotation(resolve, page) as f32 * std::f32::consts::PI / 180.);
     let br = rotate * RectF::new(Vector2F::zero(), bounds.size());
     let translate = Transform2F::from_translation(Vector2F::new(
         -br.min_x().min(br.max_x()),
@@ -89,22 +137,55 @@ pub fn render_page(backend: &mut impl Backend, resolve: &impl Resolve, page: &Pa
     let view_box = transform * translate * br;
     backend.set_view_box(view_box);
     
-    let root_transformation = transform
+    transform
         * translate
         * rotate
-        * Transform2F::row_major(SCALE, 0.0, -bounds.min_x(), 0.0, -SCALE, bounds.max_y());
-    
-    let resources = t!(page.resources());
-
-    let contents = try_opt!(page.contents.as_ref());
-    let ops = contents.operations(resolve)?;
-    let mut renderstate = RenderState::new(backend, resolve, &resources, root_transformation);
+        * Transform2F::row_major(SCALE, 0.0, -bounds.min_x(), 0.0, -SCALE, bounds.max_y())
+}
+// The operators of all parts of the contents, parsed as one stream. A part may end right
+// after a token and the next start with one, so they are kept apart by a line break.
+pub(crate) fn content_ops(contents: &Content, resolve: &impl Resolve) -> Result<Vec<Op>, PdfError> {
+    let mut data = vec![];
+    for part in contents.parts.iter() {
+        data.extend_from_slice(&t!(part.data(resolve)));
+        data.push(b'\n');
+    }
+    parse_ops(&data, resolve)
+}
+pub fn render_page(backend: &mut impl Backend, resolve: &impl Resolve, page: &PageRc, transform: Transform2F) -> Result<Transform2F, PdfError> {
+    let (root_transformation, mut errors) = draw_page(backend, resolve, page, transform, false);
+    match errors.pop() {
+        Some(e) => Err(e),
+        None => Ok(root_transformation),
+    }
+}
+// Like render_page, but an operator that fails is skipped so the rest of the page still gets
+// drawn. Returns the errors along with the transform. A page that can't be read at all
+// only has the one error that stopped it.
+pub fn render_page_best_effort(backend: &mut impl Backend, resolve: &impl Resolve, page: &PageRc, transform: Transform2F) -> (Transform2F, Vec<PdfError>) {
+    draw_page(backend, resolve, page, transform, true)
+}
+// the transform from page space and what went wrong drawing the page, which without
+// `keep_going` is the error that stopped it
+fn draw_page(backend: &mut impl Backend, resolve: &impl Resolve, page: &PageRc, transform: Transform2F, keep_going: bool) -> (Transform2F, Vec<PdfError>) {
+    let root_transformation = page_transform(backend, resolve, page, transform);
+    let (resources, ops) = match page.resources().and_then(|resources| Ok((resources, content_ops(try_opt!(page.contents.as_ref()), resolve)?))) {
+        Ok(page) => page,
+        Err(e) => return (root_transformation, vec![e]),
+    };
+    let mut errors = vec![];
+    let mut renderstate = RenderState::new(backend, resolve, resources, root_transformation);
     for (i, op) in ops.iter().enumerate() {
         debug!("op {}: {:?}", i, op);
-        renderstate.draw_op(op)?;
+        if let Err(e) = renderstate.draw_op(op) {
+            if !keep_going {
+                return (root_transformation, vec![e]);
+            }
+            warn!("skipping op {} ({:?}): {:?}", i, op, e);
+            errors.push(e);
+        }
     }
-
-    Ok(root_transformation)
+    (root_transformation, errors)
 }
 pub fn render_pattern(backend: &mut impl Backend, pattern: &Pattern, resolve: &impl Resolve) -> Result<(), PdfError> {
     match pattern {
@@ -136,24 +217,51 @@ impl Fill {
 #[derive(Debug)]
 pub struct TextSpan {
     // A rect with the origin at the baseline, a height of 1em and width that corresponds to the advance width.
+    // For vertical text, a column 1em wide centered on the origin, as long as the advance.
     pub rect: RectF,
+    // Like rect, but spanning from the descent to the ascent of the font (and shifted by the text rise).
+    pub font_rect: RectF,
 
     // width in textspace units (before applying transform)
     pub width: f32,
     // Bounding box of the rendered outline
     pub bbox: Option<RectF>,
     pub font_size: f32,
+    // advance of a space in textspace units, after font size and horizontal scaling
+    pub space_advance: f32,
+    // text rise in textspace units, positive for superscripts and negative for subscripts
+    pub rise: f32,
     #[debug(skip)]
     pub font: Option<Arc<FontEntry>>,
     pub text: String,
     pub chars: Vec<TextChar>,
     pub color: Fill,
     pub alpha: f32,
+    // text rendering mode, invisible text is usually an OCR layer. pdf has no variant for
+    // mode 7, invisible text that only adds to the clip, and rejects the content stream.
+    pub mode: TextMode,
+    // drawn within /Artifact marked content, like running headers and page numbers
+    pub artifact: bool,
 
     // apply this transform to a text draw in at the origin with the given width and font-size
     pub transform: Transform2F,
 }
 impl TextSpan {
+    pub fn decode_source(&self) -> Option<DecodeSource> {
+        self.font.as_ref().map(|f| f.decode_source)
+    }
+    // whether the text runs down the page, with lines from right to left
+    pub fn vertical(&self) -> bool {
+        self.font.as_ref().is_some_and(|f| f.vertical)
+    }
+    // advance of a space in device space, to compare gaps between spans against
+    pub fn space_width(&self) -> f32 {
+        self.space_advance * self.transform.m11().hypot(self.transform.m21())
+    }
+    // height of an em in device space, comparable between spans whatever their transform
+    pub fn device_font_size(&self) -> f32 {
+        self.font_size * self.transform.m12().hypot(self.transform.m22())
+    }
     pub fn parts(&self) -> impl Iterator<Item=Part> + '_ {
         self.chars.iter().cloned()
             .chain(std::iter::once(TextChar { offset: self.text.len(), pos: self.width, width: 0.0 }))
@@ -177,4 +285,48 @@ pub struct TextChar {
     pub offset: usize,
     pub pos: f32,
     pub width: f32,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdf::file::File;
+
+    #[test]
+    fn rotated_page_dimensions() {
+        // a landscape crop box from the page tree, on a page turned a quarter by the node above
+        // it, one below the same node that sets itself upright, one that isn't turned and one
+        // turned by itself
+        let data = testpdf::pdf_with_objects(&[
+            b"<< /Type /Catalog /Pages 2 0 R >>",
+            b"<< /Type /Pages /Kids [3 0 R 5 0 R 6 0 R] /Count 4 /MediaBox [0 0 300 300] /CropBox [10 10 210 110] >>",
+            b"<< /Type /Pages /Parent 2 0 R /Kids [4 0 R 7 0 R] /Count 2 /Rotate 90 >>",
+            b"<< /Type /Page /Parent 3 0 R >>",
+            b"<< /Type /Page /Parent 2 0 R >>",
+            b"<< /Type /Page /Parent 2 0 R /Rotate 270 >>",
+            b"<< /Type /Page /Parent 3 0 R /Rotate 0 >>",
+        ]);
+        let file = File::from_data(data).unwrap();
+        let dimensions = |n| page_dimensions(&file, &file.get_page(n).unwrap()).unwrap();
+        std::assert_eq!(dimensions(0), (100., 200.));
+        std::assert_eq!(dimensions(1), (200., 100.));
+        std::assert_eq!(dimensions(2), (200., 100.));
+        std::assert_eq!(dimensions(3), (100., 200.));
+    }
+
+    #[test]
+    fn contents_split_in_a_text_object() {
+        // the text object starts in one part and ends in the next, which starts right after a token
+        let data = testpdf::pdf_with_page(b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Contents [4 0 R 5 0 R] >>", &[
+            &testpdf::stream(b"BT /F1 10 Tf 10 10 Td (AB) Tj"),
+            &testpdf::stream(b"ET"),
+        ]);
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let ops = content_ops(page.contents.as_ref().unwrap(), &file).unwrap();
+        std::assert_eq!(ops.len(), 5);
+        assert!(matches!(ops[0], Op::BeginText));
+        assert!(matches!(ops[3], Op::TextDraw { .. }));
+        assert!(matches!(ops[4], Op::EndText));
+    }
+}
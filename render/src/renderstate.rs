@@ -3,7 +3,10 @@ use pdf::primitive::{Primitive, Dictionary};
 use pdf::content::{Op, Matrix, Point, Rect, Color, Rgb, Cmyk, Winding, FormXObject};
 use pdf::error::{PdfError, Result};
 use pdf::content::TextDrawAdjusted;
-use crate::backend::Backend;
+use crate::backend::{Backend, TjSpacing};
+#[cfg(feature = "salvage")]
+use crate::salvage::TextOp;
+use itertools::Either;
 
 use pathfinder_geometry::{
     vector::{Vector2F},
@@ -19,10 +22,17 @@ use super::{
     textstate::{TextState, Span},
     DrawMode,
     TextSpan,
+    TextChar,
     Fill,
     backend::Stroke,
 };
 
+// TJ adjustments, relative to the width of a space in the current font
+const WORD_GAP: f32 = 0.5;
+const COLUMN_GAP: f32 = 3.0;
+// TJ adjustments wider than this (in text space units) are spaces when not judged by the font
+const FIXED_GAP: f32 = 0.1;
+
 trait Cvt {
     type Out;
     fn cvt(self) -> Self::Out;
@@ -82,6 +92,24 @@ pub struct RenderState<'a, R: Resolve, B: Backend> {
     resolve: &'a R,
     resources: &'a Resources,
     backend: &'a mut B,
+    // the open marked content sequences, innermost last, including those of the content
+    // that draws this as a form
+    marked_content: Vec<MarkedContent>,
+    // how many of them were opened outside the form, its EMC can't close those
+    outer_marked_content: usize,
+}
+
+struct MarkedContent {
+    // its /ActualText, if it has one
+    actual_text: Option<ActualText>,
+    // tagged /Artifact: page furniture like running headers and page numbers
+    artifact: bool,
+}
+
+struct ActualText {
+    text: String,
+    // the replacement goes into the first span of the sequence, the others are emptied
+    emitted: bool,
 }
 
 impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
@@ -121,6 +149,8 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             resources,
             resolve,
             backend,
+            marked_content: vec![],
+            outer_marked_content: 0,
         }
     }
     fn draw(&mut self, mode: &DrawMode, fill_rule: FillRule) {
@@ -128,11 +158,27 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
         self.backend.draw(&self.current_outline, mode, fill_rule, self.graphics_state.transform);
         self.current_outline.clear();
     }
+    pub fn backend(&mut self) -> &mut B {
+        self.backend
+    }
     #[allow(unused_variables)]
     pub fn draw_op(&mut self, op: &'a Op) -> Result<()> {
         match *op {
-            Op::BeginMarkedContent { .. } => {}
-            Op::EndMarkedContent { .. } => {}
+            Op::BeginMarkedContent { ref tag, ref properties } => {
+                let actual_text = properties.as_ref()
+                    .and_then(|p| self.get_properties(p).ok())
+                    .and_then(|dict| match dict.get("ActualText") {
+                        Some(Primitive::String(s)) => Some(text_string(s.as_bytes())),
+                        _ => None,
+                    })
+                    .map(|text| ActualText { text, emitted: false });
+                self.marked_content.push(MarkedContent { actual_text, artifact: tag.as_str() == "Artifact" });
+            }
+            Op::EndMarkedContent { .. } => {
+                if self.marked_content.len() > self.outer_marked_content {
+                    self.marked_content.pop();
+                }
+            }
             Op::MarkedContentPoint { .. } => {}
             Op::Close => {
                 self.current_contour.close();
@@ -251,22 +297,7 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             Op::WordSpacing { word_space } => self.text_state.word_space = word_space,
             Op::TextScaling { horiz_scale } => self.text_state.horiz_scale = 0.01 * horiz_scale,
             Op::Leading { leading } => self.text_state.leading = leading,
-            Op::TextFont { ref name, size } => {
-                let font = match self.resources.fonts.get(name) {
-                    Some(font_ref) => {
-                        self.backend.get_font(font_ref, self.resolve)?
-                    },
-                    None => None
-                };
-                if let Some(e) = font {
-                    debug!("new font: {} (is_cid={:?})", e.name, e.is_cid);
-                    self.text_state.font_entry = Some(e);
-                    self.text_state.font_size = size;
-                } else {
-                    info!("no font {}", name);
-                    self.text_state.font_entry = None;
-                }
-            },
+            Op::TextFont { ref name, size } => self.set_font(name.as_str(), size)?,
             Op::TextRenderMode { mode } => self.text_state.mode = mode,
             Op::TextRise { rise } => self.text_state.rise = rise,
             Op::MoveTextPosition { translation } => self.text_state.translate(translation.cvt()),
@@ -278,23 +309,10 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                 });
             },
             Op::TextDrawAdjusted { ref array } => {
-                self.text(|backend, text_state, graphics_state, span| {
-                    for arg in array {
-                        match *arg {
-                            TextDrawAdjusted::Text(ref data) => {
-                                text_state.draw_text(backend, graphics_state, data.as_bytes(), span);
-                            },
-                            TextDrawAdjusted::Spacing(offset) => {
-                                // because why not PDF…
-                                let advance = text_state.advance(-0.001 * offset);
-                                if advance > 0.1 {
-                                    span.text.push_str(" ");
-                                }
-                                span.width += advance;
-                            }
-                        }
-                    }
-                });
+                self.draw_text_adjusted(array.iter().map(|arg| match *arg {
+                    TextDrawAdjusted::Text(ref data) => Either::Left(data.as_bytes()),
+                    TextDrawAdjusted::Spacing(offset) => Either::Right(offset),
+                }));
             },
             Op::XObject { ref name } => {
                 let &xobject_ref = self.resources.xobjects.get(name).ok_or(PdfError::NotFound { word: name.as_str().into()})?;
@@ -319,32 +337,127 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
         Ok(())
     }
 
+    fn set_font(&mut self, name: &str, size: f32) -> Result<()> {
+        let font = match self.resources.fonts.get(name) {
+            Some(font_ref) => {
+                self.backend.get_font(font_ref, self.resolve)?
+            },
+            None => None
+        };
+        if let Some(e) = font {
+            debug!("new font: {} (is_cid={:?})", e.name, e.is_cid);
+            self.text_state.font_entry = Some(e);
+            self.text_state.font_size = size;
+        } else {
+            info!("no font {}", name);
+            self.text_state.font_entry = None;
+        }
+        Ok(())
+    }
+
+    // strings (left) interleaved with adjustments in thousandths of an em (right), as in TJ
+    fn draw_text_adjusted<'b>(&mut self, array: impl IntoIterator<Item=Either<&'b [u8], f32>>) {
+        self.text(|backend, text_state, graphics_state, span| {
+            for arg in array {
+                match arg {
+                    Either::Left(data) => {
+                        text_state.draw_text(backend, graphics_state, data, span);
+                    },
+                    Either::Right(offset) => {
+                        // because why not PDF…
+                        let advance = text_state.advance(-0.001 * offset);
+                        if let Some(c) = tj_gap(advance, text_state.space_advance(), backend.tj_spacing()) {
+                            span.text.push(c);
+                        }
+                        span.width += advance;
+                    }
+                }
+            }
+        });
+    }
+
+    #[cfg(feature = "salvage")]
+    pub fn draw_salvaged(&mut self, op: &TextOp) -> Result<()> {
+        match *op {
+            TextOp::BeginText => self.text_state.reset_matrix(),
+            TextOp::Font(ref name, size) => self.set_font(name, size)?,
+            TextOp::Move(x, y) => self.text_state.translate(Vector2F::new(x, y)),
+            TextOp::Matrix([a, b, c, d, e, f]) => self.text_state.set_matrix(Transform2F::row_major(a, c, e, b, d, f)),
+            TextOp::NextLine => self.text_state.next_line(),
+            TextOp::Leading(leading) => self.text_state.leading = leading,
+            TextOp::WordSpacing(word_space) => self.text_state.word_space = word_space,
+            TextOp::CharSpacing(char_space) => self.text_state.char_space = char_space,
+            TextOp::Show(ref parts) => self.draw_text_adjusted(parts.iter().map(|part| match *part {
+                Either::Left(ref data) => Either::Left(&data[..]),
+                Either::Right(offset) => Either::Right(offset),
+            })),
+        }
+        Ok(())
+    }
+
     fn text(&mut self, inner: impl FnOnce(&mut B, &mut TextState, &mut GraphicsState, &mut Span)) {
         let mut span = Span::default();
         let tm = self.text_state.text_matrix;
-        let origin = tm.translation();
 
         inner(&mut self.backend, &mut self.text_state, &mut self.graphics_state, &mut span);
 
+        // the innermost /ActualText replaces everything drawn inside its sequence. Its first
+        // span carries the replacement, the ones after it are left out.
+        if let Some(actual) = self.marked_content.iter_mut().rev().find_map(|m| m.actual_text.as_mut()) {
+            if actual.emitted {
+                return;
+            }
+            span.chars.clear();
+            span.text.clear();
+            span.chars.push(TextChar { offset: 0, pos: 0., width: span.width });
+            span.text.push_str(&actual.text);
+            actual.emitted = true;
+        }
+
         let transform = self.graphics_state.transform * tm * Transform2F::from_scale(Vector2F::new(1.0, -1.0));
-        let p1 = origin;
-        let p2 = (tm * Transform2F::from_translation(Vector2F::new(span.width, self.text_state.font_size))).translation();
+        let text_to_device = self.graphics_state.transform * tm;
+        let (rise, font_size) = (self.text_state.rise, self.text_state.font_size);
+        let vertical = self.text_state.font_entry.as_ref().is_some_and(|e| e.vertical);
+        let (p1, p2, q1, q2) = if vertical {
+            // a column one em wide, centered on the current point and running down
+            let p1 = Vector2F::new(-0.5 * font_size, -span.width);
+            let p2 = Vector2F::new(0.5 * font_size, 0.);
+            (p1, p2, p1, p2)
+        } else {
+            let p1 = Vector2F::zero();
+            let p2 = Vector2F::new(span.width, font_size);
+
+            // the same, but from the descent to the ascent of the font
+            let (ascent, descent) = self.text_state.font_entry.as_ref().map_or((1.0, 0.0), |e| (e.ascent, e.descent));
+            let q1 = Vector2F::new(0., rise + descent * font_size);
+            let q2 = Vector2F::new(span.width, rise + ascent * font_size);
+            (p1, p2, q1, q2)
+        };
 
         debug!("text {}", span.text);
         self.backend.add_text(TextSpan {
-            rect: self.graphics_state.transform * RectF::from_points(p1.min(p2), p1.max(p2)),
+            rect: device_rect(text_to_device, p1, p2),
+            font_rect: device_rect(text_to_device, q1, q2),
             width: span.width,
             bbox: span.bbox.rect(),
             text: span.text,
             chars: span.chars,
             font: self.text_state.font_entry.clone(),
             font_size: self.text_state.font_size,
+            space_advance: self.text_state.space_advance(),
+            rise,
             color: self.graphics_state.fill_color,
             alpha: self.graphics_state.fill_color_alpha,
             transform,
+            mode: self.text_state.mode,
+            artifact: self.is_artifact(),
         });
     }
 
+    fn is_artifact(&self) -> bool {
+        self.marked_content.iter().any(|m| m.artifact)
+    }
+
     fn color_space(&self, name: &str) -> Result<&'a ColorSpace> {
         match name {
             "DeviceGray" => return Ok(&ColorSpace::DeviceGray),
@@ -376,6 +489,11 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             None => self.resources
         };
 
+        let ops = t!(form.operations(self.resolve));
+        // the form runs inside the marked content around it, so an /ActualText that spans
+        // the form still replaces its text once
+        let marked_content = std::mem::take(&mut self.marked_content);
+        let outer_marked_content = marked_content.len();
         let mut inner = RenderState {
             graphics_state: graphics_state,
             text_state: self.text_state.clone(),
@@ -385,17 +503,20 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             current_contour: Contour::new(),
             backend: self.backend,
             resolve: self.resolve,
+            marked_content,
+            outer_marked_content,
         };
         
-        let ops = t!(form.operations(self.resolve));
-        for (i, op) in ops.iter().enumerate() {
+        let result = ops.iter().enumerate().try_for_each(|(i, op)| {
             debug!(" form op {}: {:?}", i, op);
-            inner.draw_op(op)?;
-        }
+            inner.draw_op(op)
+        });
+        // sequences the form left open end with it
+        inner.marked_content.truncate(outer_marked_content);
+        self.marked_content = inner.marked_content;
 
-        Ok(())
+        result
     }
-    #[allow(dead_code)]
     fn get_properties<'b>(&'b self, p: &'b Primitive) -> Result<&'b Dictionary> {
         match p {
             Primitive::Dictionary(ref dict) => Ok(dict),
@@ -595,3 +716,329 @@ fn cmyk2rgb((c, m, y, k): (f32, f32, f32, f32)) -> Fill {
         1.0 - clamp(y + k),
     )
 }
+
+// The rect with corners p1 and p2 in text space, in device space. All four corners are
+// transformed, so rotated and skewed text gets its full extent. Only translation and scale,
+// as on most pages, keep it axis aligned, and then two corners are enough.
+fn device_rect(transform: Transform2F, p1: Vector2F, p2: Vector2F) -> RectF {
+    if transform.m12() == 0. && transform.m21() == 0. {
+        let (a, b) = (transform * p1, transform * p2);
+        RectF::from_points(a.min(b), a.max(b))
+    } else {
+        transform * RectF::from_points(p1.min(p2), p1.max(p2))
+    }
+}
+
+// what a TJ adjustment that moves the text by `advance` stands for, given the advance of
+// a space. judged by the font, small adjustments are kerning, larger ones separate words,
+// and anything spanning several spaces jumps to a new column. without a width for the
+// space there is nothing to judge by.
+fn tj_gap(advance: f32, space: f32, spacing: TjSpacing) -> Option<char> {
+    match spacing {
+        TjSpacing::Relative if space > 0. => {
+            if advance > COLUMN_GAP * space {
+                Some('\t')
+            } else if advance > WORD_GAP * space {
+                Some(' ')
+            } else {
+                None
+            }
+        }
+        _ => (advance > FIXED_GAP).then_some(' '),
+    }
+}
+
+// a text string outside of content streams: UTF-16BE or UTF-8 with a byte order mark,
+// otherwise PDFDocEncoding
+pub fn text_string(data: &[u8]) -> String {
+    if let Some(utf16) = data.strip_prefix(b"\xfe\xff") {
+        let units = utf16.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]]));
+        std::char::decode_utf16(units).map(|c| c.unwrap_or('\u{FFFD}')).collect()
+    } else if let Some(utf8) = data.strip_prefix(b"\xef\xbb\xbf") {
+        String::from_utf8_lossy(utf8).into_owned()
+    } else {
+        data.iter().map(|&b| pdf_doc_char(b)).collect()
+    }
+}
+
+// PDFDocEncoding agrees with Latin-1, except for accents in 0x18..0x20 and
+// punctuation and ligatures in 0x80..=0xA0
+fn pdf_doc_char(b: u8) -> char {
+    const LOW: [char; 8] = ['\u{2D8}', '\u{2C7}', '\u{2C6}', '\u{2D9}', '\u{2DD}', '\u{2DB}', '\u{2DA}', '\u{2DC}'];
+    const HIGH: [char; 33] = [
+        '\u{2022}', '\u{2020}', '\u{2021}', '\u{2026}', '\u{2014}', '\u{2013}', '\u{192}', '\u{2044}',
+        '\u{2039}', '\u{203A}', '\u{2212}', '\u{2030}', '\u{201E}', '\u{201C}', '\u{201D}', '\u{2018}',
+        '\u{2019}', '\u{201A}', '\u{2122}', '\u{FB01}', '\u{FB02}', '\u{141}', '\u{152}', '\u{160}',
+        '\u{178}', '\u{17D}', '\u{131}', '\u{142}', '\u{153}', '\u{161}', '\u{17E}', '\u{FFFD}',
+        '\u{20AC}',
+    ];
+    match b {
+        0x18 ..= 0x1F => LOW[(b - 0x18) as usize],
+        0x80 ..= 0xA0 => HIGH[(b - 0x80) as usize],
+        _ => b as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testpdf;
+    use crate::tracer::{page_text_spans, TextOptions, TraceCache};
+    use pdf::content::TextMode;
+    use pdf::file::File;
+
+    #[test]
+    fn tj_gap_relative() {
+        let space = 2.5;
+        std::assert_eq!(tj_gap(0.2, space, TjSpacing::Relative), None);
+        std::assert_eq!(tj_gap(2.0, space, TjSpacing::Relative), Some(' '));
+        std::assert_eq!(tj_gap(10.0, space, TjSpacing::Relative), Some('\t'));
+        // tightening is never a gap
+        std::assert_eq!(tj_gap(-5.0, space, TjSpacing::Relative), None);
+    }
+
+    #[test]
+    fn tj_gap_without_space_width() {
+        // falls back to the fixed threshold instead of making every gap a tab
+        std::assert_eq!(tj_gap(0.05, 0., TjSpacing::Relative), None);
+        std::assert_eq!(tj_gap(2.0, 0., TjSpacing::Relative), Some(' '));
+        std::assert_eq!(tj_gap(2.0, -1., TjSpacing::Relative), Some(' '));
+    }
+
+    #[test]
+    fn tj_gap_fixed() {
+        std::assert_eq!(tj_gap(0.05, 2.5, TjSpacing::Fixed), None);
+        std::assert_eq!(tj_gap(0.2, 2.5, TjSpacing::Fixed), Some(' '));
+        std::assert_eq!(tj_gap(10.0, 2.5, TjSpacing::Fixed), Some(' '));
+    }
+
+    // the spans of `content`, drawn in /F1 of a page. the font has the printable
+    // ASCII chars, a space is a quarter em, 2.5 at size 10, and the letters half an em
+    fn ascii_spans(content: &[u8]) -> Vec<TextSpan> {
+        let widths: Vec<String> = (0x20..=0x7A).map(|c| if c == 0x20 { "250" } else { "500" }.to_string()).collect();
+        let font = format!("<< /Type /Font /Subtype /Type3 /FontBBox [0 0 1000 1000] /FontMatrix [0.001 0 0 0.001 0 0] /CharProcs << >> /FirstChar 32 /LastChar 122 /Widths [{}] /ToUnicode 5 0 R >>", widths.join(" "));
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> >> /Contents 6 0 R >>",
+            &[
+                font.as_bytes(),
+                &testpdf::stream(b"
+                    /CIDInit /ProcSet findresource begin
+                    12 dict begin
+                    begincmap
+                    1 begincodespacerange <00> <FF> endcodespacerange
+                    1 beginbfrange <20> <7A> <0020> endbfrange
+                    endcmap
+                "),
+                &testpdf::stream(content),
+            ],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        page_text_spans(&file, &page, &TraceCache::new(), &TextOptions::default()).unwrap().spans
+    }
+
+    fn ascii_text(content: &[u8]) -> Vec<String> {
+        ascii_spans(content).into_iter().map(|span| span.text).collect()
+    }
+
+    #[test]
+    fn tj_gaps_measured_against_the_space() {
+        let text = ascii_text(b"
+            BT /F1 10 Tf 10 10 Td [(W) -100 (ord)] TJ ET
+            BT /F1 10 Tf 10 50 Td [(two) -300 (words)] TJ ET
+        ");
+        // loose kerning of a whole unit stays within the word, over half a space separates words
+        std::assert_eq!(text, ["Word", "two words"]);
+    }
+
+    #[test]
+    fn letter_spaced_word() {
+        // a gap of 2 is over half a space of 2.5, but letter spacing makes the space 4.5 wide
+        let text = ascii_text(b"
+            BT /F1 10 Tf 10 10 Td [(W) -200 (ord)] TJ ET
+            BT /F1 10 Tf 2 Tc 10 50 Td [(W) -200 (ord)] TJ ET
+        ");
+        std::assert_eq!(text, ["W ord", "Word"]);
+    }
+
+    #[test]
+    fn word_spacing() {
+        let spans = ascii_spans(b"BT /F1 10 Tf 10 10 Td (a b) Tj 5 Tw 0 40 Td (a b) Tj ET");
+        let widths: Vec<(&str, f32)> = spans.iter().map(|span| (span.text.as_str(), span.width)).collect();
+        // it widens the space, and only the space
+        std::assert_eq!(widths, [("a b", 12.5), ("a b", 17.5)]);
+    }
+
+    #[test]
+    fn footnote_marker() {
+        let spans = ascii_spans(b"BT /F1 10 Tf 10 10 Td (note) Tj 4 Ts (1) Tj 0 Ts (.) Tj ET");
+        let rises: Vec<(&str, f32)> = spans.iter().map(|span| (span.text.as_str(), span.rise)).collect();
+        std::assert_eq!(rises, [("note", 0.), ("1", 4.), (".", 0.)]);
+    }
+
+    #[test]
+    fn fill_colors() {
+        let spans = ascii_spans(b"BT /F1 10 Tf 10 10 Td 1 0 0 rg (red) Tj 0 40 Td 0 0 1 rg (blue) Tj ET");
+        let colors: Vec<(&str, &Fill)> = spans.iter().map(|span| (span.text.as_str(), &span.color)).collect();
+        std::assert_eq!(colors, [("red", &Fill::Solid(1., 0., 0.)), ("blue", &Fill::Solid(0., 0., 1.))]);
+    }
+
+    #[test]
+    fn title_and_body_sizes() {
+        // the title once set in 24, once in 12 scaled by two
+        let spans = ascii_spans(b"BT /F1 24 Tf 10 150 Td (Title) Tj ET q 2 0 0 2 0 0 cm BT /F1 12 Tf 5 60 Td (Title) Tj ET Q BT /F1 10 Tf 10 50 Td (body) Tj ET");
+        let sizes: Vec<f32> = spans.iter().map(|span| span.device_font_size()).collect();
+        assert!((sizes[0] - sizes[1]).abs() < 1e-4);
+        assert!((sizes[0] / sizes[2] - 2.4).abs() < 1e-4);
+    }
+
+    #[test]
+    fn scale_in_the_font_size_or_the_matrix() {
+        // the same lines, once scaled by Tf and once by Tm
+        let by_size = ascii_spans(b"BT /F1 12 Tf 1 0 0 1 10 100 Tm (one) Tj 1 0 0 1 10 86 Tm (two) Tj ET");
+        let by_matrix = ascii_spans(b"BT /F1 1 Tf 12 0 0 12 10 100 Tm (one) Tj 12 0 0 12 10 86 Tm (two) Tj ET");
+        for (a, b) in by_size.iter().zip(by_matrix.iter()) {
+            assert!((a.rect.origin() - b.rect.origin()).length() < 1e-4);
+            assert!((a.rect.size() - b.rect.size()).length() < 1e-4);
+            assert!((a.device_font_size() - b.device_font_size()).abs() < 1e-4);
+        }
+        std::assert_eq!(by_matrix.len(), 2);
+    }
+
+    #[test]
+    fn running_header_artifact() {
+        // the header is an artifact, the body is tagged as a paragraph in which a span is one again
+        let spans = ascii_spans(b"/Artifact << /Type /Pagination >> BDC BT /F1 10 Tf 10 180 Td (Header) Tj ET EMC /P << /MCID 0 >> BDC BT /F1 10 Tf 10 100 Td (Body) Tj ET /Artifact BMC BT /F1 10 Tf 10 90 Td (x) Tj ET EMC BT /F1 10 Tf 10 80 Td (text) Tj ET EMC");
+        let artifacts: Vec<(&str, bool)> = spans.iter().map(|span| (span.text.as_str(), span.artifact)).collect();
+        std::assert_eq!(artifacts, [("Header", true), ("Body", false), ("x", true), ("text", false)]);
+    }
+
+    #[test]
+    fn type3_glyph_widths() {
+        // no /Widths, the glyph procedures set them with d0 and d1
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> >> /Contents 7 0 R >>",
+            &[
+                b"<< /Type /Font /Subtype /Type3 /FontBBox [0 0 1000 1000] /FontMatrix [0.001 0 0 0.001 0 0] /CharProcs << /A 5 0 R /B 6 0 R >> /Encoding << /Differences [65 /A /B] >> >>",
+                &testpdf::stream(b"500 0 d0 0 0 m 500 0 l 250 700 l f"),
+                &testpdf::stream(b"750 0 0 0 750 700 d1 0 0 750 700 re f"),
+                &testpdf::stream(b"BT /F1 10 Tf 10 10 Td (AA) Tj 0 40 Td (BB) Tj ET"),
+            ],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let spans = page_text_spans(&file, &page, &TraceCache::new(), &TextOptions::default()).unwrap().spans;
+        let texts: Vec<&str> = spans.iter().map(|span| span.text.as_str()).collect();
+        std::assert_eq!(texts, ["AA", "BB"]);
+        assert!((spans[0].width - 10.).abs() < 1e-4);
+        assert!((spans[1].width - 15.).abs() < 1e-4);
+        // in mm on the page
+        assert!((spans[1].rect.width() - 15. * 25.4 / 72.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn type3_font_matrix() {
+        // a hundred units to the em, so the widths of 50 are half an em as in abc_font
+        let [_, to_unicode] = testpdf::abc_font(4);
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> >> /Contents 6 0 R >>",
+            &[
+                b"<< /Type /Font /Subtype /Type3 /FontBBox [0 0 100 100] /FontMatrix [0.01 0 0 0.01 0 0] /CharProcs << >> /FirstChar 65 /LastChar 67 /Widths [50 50 50] /ToUnicode 5 0 R >>",
+                &to_unicode,
+                &testpdf::stream(b"BT /F1 10 Tf 10 10 Td (AB) Tj (C) Tj ET"),
+            ],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let spans = page_text_spans(&file, &page, &TraceCache::new(), &TextOptions::default()).unwrap().spans;
+        let texts: Vec<&str> = spans.iter().map(|span| span.text.as_str()).collect();
+        std::assert_eq!(texts, ["AB", "C"]);
+        assert!((spans[0].width - 10.).abs() < 1e-4);
+        assert!((spans[1].width - 5.).abs() < 1e-4);
+        assert!((spans[0].rect.width() - 10. * 25.4 / 72.).abs() < 1e-3);
+        // C is set where B ends
+        assert!((spans[1].rect.min_x() - spans[0].rect.max_x()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn invisible_text_over_a_scan() {
+        let [type3, to_unicode] = testpdf::abc_font(4);
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> >> /Contents 6 0 R >>",
+            &[
+                &type3,
+                &to_unicode,
+                // the OCR layer, then a visible caption
+                &testpdf::stream(b"BT /F1 10 Tf 3 Tr 10 10 Td (AB) Tj 0 Tr 0 20 Td (C) Tj ET"),
+            ],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let spans = page_text_spans(&file, &page, &TraceCache::new(), &TextOptions::default()).unwrap().spans;
+        let modes: Vec<(&str, TextMode)> = spans.iter().map(|span| (span.text.as_str(), span.mode)).collect();
+        std::assert_eq!(modes, [("AB", TextMode::Invisible), ("C", TextMode::Fill)]);
+    }
+
+    #[test]
+    fn text_strings() {
+        std::assert_eq!(text_string(b"\xfe\xff\x00A\xd8\x3d\xde\x00"), "A\u{1F600}");
+        // an unpaired surrogate
+        std::assert_eq!(text_string(b"\xfe\xff\xd8\x3d\x00B"), "\u{FFFD}B");
+        std::assert_eq!(text_string("\u{FEFF}café".as_bytes()), "café");
+        // PDFDocEncoding, Latin-1 but for some accents, punctuation and the euro sign
+        std::assert_eq!(text_string(b"\x18\x80\x8d\xa0caf\xe9"), "\u{2D8}\u{2022}\u{201C}\u{20AC}café");
+    }
+
+    #[test]
+    fn device_rect_of_transformed_text() {
+        let (p1, p2) = (Vector2F::new(0., 0.), Vector2F::new(4., 2.));
+        // scaled and flipped like a page, which takes the two corners
+        let page = Transform2F::row_major(2., 0., 10., 0., -2., 100.);
+        std::assert_eq!(device_rect(page, p1, p2), RectF::from_points(Vector2F::new(10., 96.), Vector2F::new(18., 100.)));
+        std::assert_eq!(device_rect(page, p1, p2), page * RectF::from_points(p1, p2));
+        // a quarter turn
+        let turned = Transform2F::row_major(0., -1., 0., 1., 0., 0.);
+        std::assert_eq!(device_rect(turned, p1, p2), RectF::from_points(Vector2F::new(-2., 0.), Vector2F::new(0., 4.)));
+    }
+
+    #[test]
+    fn actual_text_around_a_form() {
+        let [type3, to_unicode] = testpdf::abc_font(4);
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> /XObject << /X1 7 0 R >> >> /Contents 6 0 R >>",
+            &[
+                &type3,
+                &to_unicode,
+                &testpdf::stream(b"
+                    BT /F1 10 Tf 10 10 Td (A) Tj ET
+                    /Span << /ActualText (Hi) >> BDC
+                    /X1 Do
+                    BT /F1 10 Tf 10 50 Td (B) Tj ET
+                    EMC
+                    BT /F1 10 Tf 10 90 Td (C) Tj ET
+                "),
+                // with an EMC of its own, which can't end the sequence around it
+                &testpdf::stream_with("/Type /XObject /Subtype /Form /BBox [0 0 200 200]", b"
+                    BT /F1 10 Tf 10 30 Td (AB) Tj ET
+                    EMC
+                "),
+            ],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let spans = page_text_spans(&file, &page, &TraceCache::new(), &TextOptions::default()).unwrap().spans;
+        let text: Vec<&str> = spans.iter().map(|span| span.text.as_str()).collect();
+        // the replacement goes into the form's text, and the page's text after it stays replaced
+        std::assert_eq!(text, ["A", "Hi", "C"]);
+    }
+
+    #[test]
+    fn actual_text_of_a_ligature() {
+        // a single glyph, C here, standing for three letters
+        let spans = ascii_spans(b"BT /F1 10 Tf 10 10 Td (o) Tj /Span << /ActualText (ffi) >> BDC (C) Tj EMC (ce) Tj ET");
+        let text: Vec<&str> = spans.iter().map(|span| span.text.as_str()).collect();
+        std::assert_eq!(text, ["o", "ffi", "ce"]);
+        // as wide as the glyph
+        assert!((spans[1].width - 5.).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,329 @@
+//! Last-resort text recovery for content streams that the operator parser rejects.
+//!
+//! Instead of parsing the whole stream, this scans the raw bytes for the text operators
+//! (`BT`, `Tf`, `Td`, `TD`, `Tm`, `T*`, `TL`, `Tw`, `Tc`, `Tj`, `TJ`, `'` and `"`) and their
+//! operands, and ignores everything else. Graphics state (`cm`, `q`, `Q`, …) is not tracked,
+//! so positions can be off, and a truncated string or array ends the scan.
+//! The result is lossy and best-effort: it only recovers *some* text.
+
+use itertools::Either;
+use pdf::object::*;
+use pdf::error::PdfError;
+use pathfinder_geometry::transform2d::Transform2F;
+use crate::{Backend, renderstate::RenderState, page_transform};
+
+#[derive(Debug, PartialEq)]
+pub enum TextOp {
+    BeginText,
+    Font(String, f32),
+    Move(f32, f32),
+    Matrix([f32; 6]),
+    NextLine,
+    Leading(f32),
+    WordSpacing(f32),
+    CharSpacing(f32),
+    // strings (left) interleaved with adjustments (right), as in TJ
+    Show(Vec<Either<Vec<u8>, f32>>),
+}
+
+enum Operand {
+    Number(f32),
+    Name(String),
+    Str(Vec<u8>),
+    Array(Vec<Either<Vec<u8>, f32>>),
+}
+
+/// Render whatever text can be found in the content streams of `page`, without parsing them properly.
+pub fn salvage_page(backend: &mut impl Backend, resolve: &impl Resolve, page: &PageRc, transform: Transform2F) -> Result<Transform2F, PdfError> {
+    let root_transformation = page_transform(backend, resolve, page, transform);
+    let resources = t!(page.resources());
+
+    let contents = try_opt!(page.contents.as_ref());
+    let mut data = vec![];
+    for part in contents.parts.iter() {
+        match part.data(resolve) {
+            Ok(part) => data.extend_from_slice(&part),
+            Err(e) => warn!("skipping undecodable content stream: {:?}", e),
+        }
+        data.push(b'\n');
+    }
+
+    let ops = text_ops(&data);
+    let mut renderstate = RenderState::new(backend, resolve, &resources, root_transformation);
+    for (i, op) in ops.iter().enumerate() {
+        debug!("salvaged op {}: {:?}", i, op);
+        if let Err(e) = renderstate.draw_salvaged(op) {
+            warn!("salvaged op {} failed: {:?}", i, e);
+        }
+    }
+
+    Ok(root_transformation)
+}
+
+pub fn text_ops(data: &[u8]) -> Vec<TextOp> {
+    let mut ops = vec![];
+    let mut operands = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        match data[pos] {
+            b'%' => {
+                while pos < data.len() && !matches!(data[pos], b'\r' | b'\n') {
+                    pos += 1;
+                }
+            }
+            b'(' => match literal_string(data, pos) {
+                Some((s, end)) => {
+                    operands.push(Operand::Str(s));
+                    pos = end;
+                }
+                None => break,
+            },
+            b'<' if data.get(pos + 1) == Some(&b'<') => pos += 2,
+            b'>' if data.get(pos + 1) == Some(&b'>') => pos += 2,
+            b'<' => match hex_string(data, pos) {
+                Some((s, end)) => {
+                    operands.push(Operand::Str(s));
+                    pos = end;
+                }
+                None => break,
+            },
+            b'[' => match array(data, pos) {
+                Some((a, end)) => {
+                    operands.push(Operand::Array(a));
+                    pos = end;
+                }
+                None => break,
+            },
+            b'/' => {
+                let (word, end) = regular(data, pos + 1);
+                operands.push(Operand::Name(String::from_utf8_lossy(word).into_owned()));
+                pos = end;
+            }
+            b if is_regular(b) => {
+                let (word, end) = regular(data, pos);
+                match number(word) {
+                    Some(n) => operands.push(Operand::Number(n)),
+                    None => {
+                        operator(word, &operands, &mut ops);
+                        operands.clear();
+                    }
+                }
+                pos = end;
+            }
+            _ => pos += 1,
+        }
+    }
+    ops
+}
+
+fn operator(word: &[u8], operands: &[Operand], ops: &mut Vec<TextOp>) {
+    use Operand::*;
+    match (word, operands) {
+        (b"BT", _) => ops.push(TextOp::BeginText),
+        (b"Tf", [.., Name(name), Number(size)]) => ops.push(TextOp::Font(name.clone(), *size)),
+        (b"Td", [.., Number(x), Number(y)]) => ops.push(TextOp::Move(*x, *y)),
+        (b"TD", [.., Number(x), Number(y)]) => {
+            ops.push(TextOp::Leading(-*y));
+            ops.push(TextOp::Move(*x, *y));
+        }
+        (b"Tm", [.., Number(a), Number(b), Number(c), Number(d), Number(e), Number(f)]) => {
+            ops.push(TextOp::Matrix([*a, *b, *c, *d, *e, *f]));
+        }
+        (b"T*", _) => ops.push(TextOp::NextLine),
+        (b"TL", [.., Number(leading)]) => ops.push(TextOp::Leading(*leading)),
+        (b"Tw", [.., Number(word_space)]) => ops.push(TextOp::WordSpacing(*word_space)),
+        (b"Tc", [.., Number(char_space)]) => ops.push(TextOp::CharSpacing(*char_space)),
+        (b"Tj", [.., Str(s)]) => ops.push(TextOp::Show(vec![Either::Left(s.clone())])),
+        (b"TJ", [.., Array(a)]) => ops.push(TextOp::Show(a.clone())),
+        (b"'", [.., Str(s)]) => {
+            ops.push(TextOp::NextLine);
+            ops.push(TextOp::Show(vec![Either::Left(s.clone())]));
+        }
+        (b"\"", [.., Number(word_space), Number(char_space), Str(s)]) => {
+            ops.push(TextOp::WordSpacing(*word_space));
+            ops.push(TextOp::CharSpacing(*char_space));
+            ops.push(TextOp::NextLine);
+            ops.push(TextOp::Show(vec![Either::Left(s.clone())]));
+        }
+        _ => {}
+    }
+}
+
+fn is_regular(b: u8) -> bool {
+    !matches!(b, b'\0' | b'\t' | b'\n' | b'\x0c' | b'\r' | b' ' | b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%')
+}
+
+fn regular(data: &[u8], start: usize) -> (&[u8], usize) {
+    let end = data[start..].iter().position(|&b| !is_regular(b)).map_or(data.len(), |n| start + n);
+    (&data[start..end], end)
+}
+
+fn number(word: &[u8]) -> Option<f32> {
+    if !word.iter().all(|&b| matches!(b, b'0'..=b'9' | b'+' | b'-' | b'.')) {
+        return None;
+    }
+    std::str::from_utf8(word).ok()?.parse().ok()
+}
+
+// `start` points at the opening parenthesis
+fn literal_string(data: &[u8], start: usize) -> Option<(Vec<u8>, usize)> {
+    let mut out = vec![];
+    let mut depth = 0;
+    let mut pos = start + 1;
+    loop {
+        let b = *data.get(pos)?;
+        pos += 1;
+        match b {
+            b'(' => {
+                depth += 1;
+                out.push(b);
+            }
+            b')' if depth == 0 => return Some((out, pos)),
+            b')' => {
+                depth -= 1;
+                out.push(b);
+            }
+            b'\\' => {
+                let e = *data.get(pos)?;
+                pos += 1;
+                match e {
+                    b'n' => out.push(b'\n'),
+                    b'r' => out.push(b'\r'),
+                    b't' => out.push(b'\t'),
+                    b'b' => out.push(b'\x08'),
+                    b'f' => out.push(b'\x0c'),
+                    b'0'..=b'7' => {
+                        let mut n = (e - b'0') as u32;
+                        for _ in 0 .. 2 {
+                            match data.get(pos) {
+                                Some(&d @ b'0'..=b'7') => {
+                                    n = n * 8 + (d - b'0') as u32;
+                                    pos += 1;
+                                }
+                                _ => break,
+                            }
+                        }
+                        out.push(n as u8);
+                    }
+                    // line continuation
+                    b'\r' => {
+                        if data.get(pos) == Some(&b'\n') {
+                            pos += 1;
+                        }
+                    }
+                    b'\n' => {}
+                    e => out.push(e),
+                }
+            }
+            b => out.push(b),
+        }
+    }
+}
+
+// `start` points at the opening angle bracket
+fn hex_string(data: &[u8], start: usize) -> Option<(Vec<u8>, usize)> {
+    let end = start + 1 + data[start + 1..].iter().position(|&b| b == b'>')?;
+    let digits: Vec<u8> = data[start + 1..end].iter()
+        .filter_map(|&b| (b as char).to_digit(16))
+        .map(|d| d as u8)
+        .collect();
+    let out = digits.chunks(2)
+        .map(|c| c[0] << 4 | c.get(1).copied().unwrap_or(0))
+        .collect();
+    Some((out, end + 1))
+}
+
+// `start` points at the opening bracket. Only strings and numbers are kept.
+fn array(data: &[u8], start: usize) -> Option<(Vec<Either<Vec<u8>, f32>>, usize)> {
+    let mut out = vec![];
+    let mut pos = start + 1;
+    loop {
+        match *data.get(pos)? {
+            b']' => return Some((out, pos + 1)),
+            b'(' => {
+                let (s, end) = literal_string(data, pos)?;
+                out.push(Either::Left(s));
+                pos = end;
+            }
+            b'<' => {
+                let (s, end) = hex_string(data, pos)?;
+                out.push(Either::Left(s));
+                pos = end;
+            }
+            b if is_regular(b) => {
+                let (word, end) = regular(data, pos);
+                if let Some(n) = number(word) {
+                    out.push(Either::Right(n));
+                }
+                pos = end;
+            }
+            _ => pos += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn show(s: &[u8]) -> TextOp {
+        TextOp::Show(vec![Either::Left(s.to_vec())])
+    }
+
+    #[test]
+    fn text_operators() {
+        let ops = text_ops(b"BT /F1 12 Tf 10 20 Td (Hello) Tj [(W) -250 <6F72> 3.5 (ld)] TJ 1 0 0 1 50 60 Tm 2 Tw 0.5 Tc ET");
+        std::assert_eq!(ops, [
+            TextOp::BeginText,
+            TextOp::Font("F1".into(), 12.),
+            TextOp::Move(10., 20.),
+            show(b"Hello"),
+            TextOp::Show(vec![
+                Either::Left(b"W".to_vec()),
+                Either::Right(-250.),
+                Either::Left(b"or".to_vec()),
+                Either::Right(3.5),
+                Either::Left(b"ld".to_vec()),
+            ]),
+            TextOp::Matrix([1., 0., 0., 1., 50., 60.]),
+            TextOp::WordSpacing(2.),
+            TextOp::CharSpacing(0.5),
+        ]);
+    }
+
+    #[test]
+    fn next_line_operators() {
+        let ops = text_ops(b"12 TL 0 -14 TD (a) ' 1 2 (b) \" T*");
+        std::assert_eq!(ops, [
+            TextOp::Leading(12.),
+            TextOp::Leading(14.),
+            TextOp::Move(0., -14.),
+            TextOp::NextLine,
+            show(b"a"),
+            TextOp::WordSpacing(1.),
+            TextOp::CharSpacing(2.),
+            TextOp::NextLine,
+            show(b"b"),
+            TextOp::NextLine,
+        ]);
+    }
+
+    #[test]
+    fn other_operators_are_skipped() {
+        // the operands of an operator that isn't kept don't carry over to the next
+        let ops = text_ops(b"q 1 0 0 1 5 5 cm /P << /MCID 0 >> BDC 7 Tj (a) Tj EMC Q\n% (comment) Tj\n(b) Tj");
+        std::assert_eq!(ops, [show(b"a"), show(b"b")]);
+    }
+
+    #[test]
+    fn strings() {
+        let ops = text_ops(b"(a\\(b\\) \\101\\n(nested) \\\nend) Tj <414> Tj");
+        std::assert_eq!(ops, [show(b"a(b) A\n(nested) end"), show(b"\x41\x40")]);
+    }
+
+    #[test]
+    fn truncated_stream() {
+        std::assert_eq!(text_ops(b"(a) Tj (unterminated Tj (b) Tj"), [show(b"a")]);
+        std::assert_eq!(text_ops(b"(a) Tj [(b) 10"), [show(b"a")]);
+    }
+}
@@ -0,0 +1,83 @@
+// Small pdf files for the tests, with a page that has the font 4 0 R as /F1
+
+use pdf::file::File;
+use pdf::font::Font as PdfFont;
+use pdf::object::MaybeRef;
+
+// a file of the catalog, the page tree and its page, followed by `objects` from 4 0 obj on
+pub fn pdf(objects: &[&[u8]]) -> Vec<u8> {
+    pdf_with_page(b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> >> >>", objects)
+}
+
+// like pdf, with `page` as 3 0 obj
+pub fn pdf_with_page(page: &[u8], objects: &[&[u8]]) -> Vec<u8> {
+    let head: [&[u8]; 3] = [
+        b"<< /Type /Catalog /Pages 2 0 R >>",
+        b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+        page,
+    ];
+    pdf_with_objects(&head.iter().chain(objects).copied().collect::<Vec<_>>())
+}
+
+// a file of `objects` from 1 0 obj on, the first of them the catalog
+pub fn pdf_with_objects(objects: &[&[u8]]) -> Vec<u8> {
+    pdf_with_trailer(objects, "")
+}
+
+// the same, with more `entries` in its trailer
+pub fn pdf_with_trailer(objects: &[&[u8]], entries: &str) -> Vec<u8> {
+    let mut data = b"%PDF-1.7\n".to_vec();
+    let mut offsets = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(data.len());
+        data.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+        data.extend_from_slice(object);
+        data.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = data.len();
+    data.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1).as_bytes());
+    for offset in offsets.iter() {
+        data.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    data.extend_from_slice(format!("trailer\n<< {} /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", entries, offsets.len() + 1, xref).as_bytes());
+    data
+}
+
+// an uncompressed stream object
+pub fn stream(content: &[u8]) -> Vec<u8> {
+    stream_with("", content)
+}
+
+// the same, with more `entries` in its dictionary
+pub fn stream_with(entries: &str, content: &[u8]) -> Vec<u8> {
+    let mut data = format!("<< {} /Length {} >>\nstream\n", entries, content.len()).into_bytes();
+    data.extend_from_slice(content);
+    data.extend_from_slice(b"\nendstream");
+    data
+}
+
+// the file of pdf(objects), and its font /F1
+pub fn font(objects: &[&[u8]]) -> (File<Vec<u8>>, MaybeRef<PdfFont>) {
+    let file = File::from_data(pdf(objects)).unwrap();
+    let page = file.get_page(0).unwrap();
+    let font = page.resources().unwrap().fonts.get("F1").unwrap().clone();
+    (file, font)
+}
+
+// A TrueType font of a triangle as A and a space, 1000 units to the em
+pub fn truetype() -> &'static [u8] {
+    include_bytes!("testdata/minimal.ttf")
+}
+
+// A Type3 font without glyphs whose codes A to C are half an em wide, and its ToUnicode CMap
+// mapping them to the same letters, as the objects `n` and `n + 1`
+pub fn abc_font(n: usize) -> [Vec<u8>; 2] {
+    abc_font_mapping(n, "1 beginbfrange <41> <43> <0041> endbfrange")
+}
+
+// the same, with `mapping` as the bfchar and bfrange sections of the CMap
+pub fn abc_font_mapping(n: usize, mapping: &str) -> [Vec<u8>; 2] {
+    let font = format!("<< /Type /Font /Subtype /Type3 /FontBBox [0 0 1000 1000] /FontMatrix [0.001 0 0 0.001 0 0] /CharProcs << >> /FirstChar 65 /LastChar 67 /Widths [500 500 500] /ToUnicode {} 0 R >>", n + 1);
+    let cmap = format!("begincmap 1 begincodespacerange <00> <FF> endcodespacerange {} endcmap", mapping);
+    [font.into_bytes(), stream(cmap.as_bytes())]
+}
@@ -10,13 +10,19 @@ use super::{
     DrawMode,
     Backend,
     TextChar,
+    MissingFont,
+    Undecoded,
 };
+use pdf_encoding::Encoding;
 use std::convert::TryInto;
 use pdf::content::TextMode;
 use std::sync::Arc;
 use itertools::Either;
 use istring::SmallString;
 
+// the default vertical origin of a glyph, 0.88 em above its bottom (from /DW2 [880 -1000])
+const VERTICAL_ORIGIN: f32 = 0.88;
+
 #[derive(Clone)]
 pub struct TextState {
     pub text_matrix: Transform2F, // tracks current glyph
@@ -65,34 +71,45 @@ impl TextState {
         self.line_matrix = m;
     }
     pub fn draw_text(&mut self, backend: &mut impl Backend, gs: &GraphicsState, data: &[u8], span: &mut Span) {
+        if self.font_entry.is_none() {
+            self.draw_missing_font(backend.missing_font(), data, span);
+        }
+        // a handle of its own, so the text state can move while the glyphs are decoded
         let e = match self.font_entry {
-            Some(ref e) => e,
+            Some(ref e) => e.clone(),
             None => {
                 debug!("no font set");
                 return;
             }
         };
 
-        let codepoints = if e.is_cid {
-            Either::Left(data.chunks_exact(2).map(|s| u16::from_be_bytes(s.try_into().unwrap())))
+        let codepoints = if let Some(ref code_map) = e.code_map {
+            Either::Left(Either::Left(code_map.codes(data)))
+        } else if e.is_cid {
+            Either::Left(Either::Right(data.chunks_exact(2).map(|s| u16::from_be_bytes(s.try_into().unwrap()) as u32)))
         } else {
-            Either::Right(data.iter().map(|&b| b as u16))
+            Either::Right(data.iter().map(|&b| b as u32))
         };
 
         let glyphs = codepoints.map(|cid| {
+            // the tables only hold two byte codes, longer ones are left undecoded
+            let code = match u16::try_from(cid) {
+                Ok(code) => code,
+                Err(_) => return (cid, None, None),
+            };
             match e.encoding {
                 TextEncoding::CID(None) => {
-                    let unicode = std::char::from_u32(cid as u32).map(|c| SmallString::from(c));
-                    (cid, Some(GlyphId(cid as u32)), unicode)
+                    let unicode = std::char::from_u32(cid).map(SmallString::from);
+                    (cid, Some(GlyphId(cid)), unicode)
                 },
                 TextEncoding::CID(Some(ref to_unicode)) => {
-                    match to_unicode.get(&cid) {
+                    match to_unicode.get(&code) {
                         Some(&(gid, ref unicode)) => (cid, gid, Some(unicode.clone())),
                         None => (cid, None, None)
                     }
                 },
                 TextEncoding::Cmap(ref cmap) => {
-                    match cmap.get(&cid) {
+                    match cmap.get(&code) {
                         Some(&(gid, ref unicode)) => (cid, Some(gid), unicode.clone()),
                         None => (cid, None, None)
                     }
@@ -112,15 +129,25 @@ impl TextState {
             TextMode::Stroke => Some(DrawMode::Stroke(gs.stroke_color, gs.stroke_color_alpha, gs.stroke())),
             TextMode::StrokeAndClip => Some(DrawMode::Stroke(gs.stroke_color, gs.stroke_color_alpha, gs.stroke())),
         };
-        let e = self.font_entry.as_ref().expect("no font");
+        let undecoded = backend.undecoded();
+        let wide_codes = e.is_cid || e.code_map.is_some();
 
         let tr = Transform2F::row_major(
             self.horiz_scale * self.font_size, 0., 0.,
             0., self.font_size, self.rise
-        ) * e.font.font_matrix();
+        ) * e.font_matrix;
         
         for (cid, gid, unicode) in glyphs {
-            let is_space = matches!(e.encoding, TextEncoding::Cmap(_)) && unicode.as_deref() == Some(" ");
+            let unicode = u16::try_from(cid).ok().and_then(|code| e.unicode_overrides.get(&code)).cloned().or(unicode);
+            // word spacing only applies to the single-byte code 32, whatever it maps to,
+            // and that code still separates words when the font doesn't say what it is.
+            let is_code_32 = !e.is_cid && cid == 32;
+            let word_space = if is_code_32 { self.word_space } else { 0. };
+            let unicode = match unicode {
+                None if is_code_32 => Some(SmallString::from(' ')),
+                unicode => unicode,
+            };
+            let is_space = unicode.as_deref() == Some(" ");
 
             //debug!("cid {} -> gid {:?} {:?}", cid, gid, unicode);
             let gid = match gid {
@@ -130,33 +157,49 @@ impl TextState {
                     GlyphId(0)
                 } // lets hope that works…
             };
-            let glyph = e.font.glyph(gid);
-            let width: f32 = e.widths.as_ref().map(|w| w.get(cid as usize) * 0.001 * self.horiz_scale * self.font_size)
+            let glyph = e.font.as_ref().and_then(|f| f.glyph(gid));
+            // widths are indexed by cid, which only an embedded CMap tells apart from the code
+            let width_index = e.code_map.as_ref().and_then(|m| m.cid(cid)).unwrap_or(cid) as usize;
+            let width: f32 = e.advance_width(width_index).map(|w| w * self.horiz_scale * self.font_size)
                 .or_else(|| glyph.as_ref().map(|g| tr.m11() * g.metrics.advance))
                 .unwrap_or(0.0);
             
             if is_space {
-                let advance = (self.char_space + self.word_space) * self.horiz_scale + width;
-                self.text_matrix = self.text_matrix * Transform2F::from_translation(Vector2F::new(advance, 0.));
+                let advance = self.glyph_advance(width, word_space);
+                self.move_by(advance);
                 span.width += advance;
                 span.text.push(' ');
                 continue;
             }
             if let Some(glyph) = glyph {
-                let transform = gs.transform * self.text_matrix * tr;
+                // vertical glyphs hang below the current point, centered on it
+                let origin = if e.vertical {
+                    Transform2F::from_translation(Vector2F::new(-0.5 * width, -VERTICAL_ORIGIN * self.font_size))
+                } else {
+                    Transform2F::default()
+                };
+                let transform = gs.transform * self.text_matrix * origin * tr;
                 if glyph.path.len() != 0 {
                     span.bbox.add(gs.transform * transform * glyph.path.bounds());
                     if let Some(ref draw_mode) = draw_mode {
                         backend.draw_glyph(&glyph, draw_mode, transform);
                     }
                 }
-            } else {
+            } else if e.font.is_some() {
                 debug!("no glyph for gid {:?}", gid);
             }
-            let advance = self.char_space * self.horiz_scale + width;
-            self.text_matrix = self.text_matrix * Transform2F::from_translation(Vector2F::new(advance, 0.));
+            let advance = self.glyph_advance(width, word_space);
+            self.move_by(advance);
             
             let offset = span.text.len();
+            let unicode = match (unicode, undecoded) {
+                (Some(unicode), _) => Some(unicode),
+                (None, Undecoded::Drop) => None,
+                (None, Undecoded::Replacement) => Some(SmallString::from('\u{FFFD}')),
+                (None, Undecoded::Hex) if wide_codes => Some(format!("<{:04X}>", cid).as_str().into()),
+                (None, Undecoded::Hex) => Some(format!("<{:02X}>", cid).as_str().into()),
+                (None, Undecoded::PrivateUse) => 0xF0000u32.checked_add(cid).and_then(std::char::from_u32).map(SmallString::from),
+            };
             if let Some(s) = unicode {
                 span.text.push_str(&*s);
                 span.chars.push(TextChar {
@@ -168,10 +211,68 @@ impl TextState {
             span.width += advance;
         }
     }
+    fn draw_missing_font(&mut self, missing_font: MissingFont, data: &[u8], span: &mut Span) {
+        let standard = match missing_font {
+            MissingFont::Drop => return,
+            MissingFont::Placeholder => None,
+            MissingFont::Guess => Encoding::AdobeStandard.to(Encoding::Unicode),
+        };
+        // without metrics, every code is assumed to be half an em wide
+        let width = 0.5 * self.font_size * self.horiz_scale;
+        for &b in data {
+            let c = standard.as_ref()
+                .and_then(|t| t.translate(b as u32))
+                .and_then(std::char::from_u32)
+                .unwrap_or('\u{FFFD}');
+            span.chars.push(TextChar {
+                offset: span.text.len(),
+                pos: span.width,
+                width
+            });
+            span.text.push(c);
+
+            let word_space = if b == 32 { self.word_space } else { 0. };
+            let advance = self.glyph_advance(width, word_space);
+            self.move_by(advance);
+            span.width += advance;
+        }
+    }
+    fn is_vertical(&self) -> bool {
+        self.font_entry.as_ref().is_some_and(|e| e.vertical)
+    }
+    // how far a glyph moves the current point along the writing direction.
+    // vertical glyphs take the default vertical advance of one em, without horizontal scaling.
+    fn glyph_advance(&self, width: f32, word_space: f32) -> f32 {
+        if self.is_vertical() {
+            self.font_size + self.char_space + word_space
+        } else {
+            (self.char_space + word_space) * self.horiz_scale + width
+        }
+    }
+    // move the current point forward, to the right or down for vertical writing
+    fn move_by(&mut self, advance: f32) {
+        let v = if self.is_vertical() { Vector2F::new(0., -advance) } else { Vector2F::new(advance, 0.) };
+        self.text_matrix = self.text_matrix * Transform2F::from_translation(v);
+    }
+    // advance of a space in text space units, guessing a quarter em if the font doesn't tell.
+    // character spacing applies to it like to any other glyph, so letter-spaced text
+    // doesn't turn every gap into a word break.
+    pub fn space_advance(&self) -> f32 {
+        if self.is_vertical() {
+            return self.font_size + self.char_space;
+        }
+        let em = self.font_entry.as_ref().and_then(|e| e.space_width).unwrap_or(0.25);
+        (em * self.font_size + self.char_space) * self.horiz_scale
+    }
     pub fn advance(&mut self, delta: f32) -> f32 {
         //debug!("advance by {}", delta);
-        let advance = delta * self.font_size * self.horiz_scale;
-        self.text_matrix = self.text_matrix * Transform2F::from_translation(Vector2F::new(advance, 0.));
+        // a positive adjustment moves vertical text down, which is forward
+        let advance = if self.is_vertical() {
+            -delta * self.font_size
+        } else {
+            delta * self.font_size * self.horiz_scale
+        };
+        self.move_by(advance);
         advance
     }
 }
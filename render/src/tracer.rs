@@ -1,4 +1,5 @@
-use crate::{TextSpan, DrawMode, Backend, FontEntry, Fill};
+use crate::{TextSpan, DrawMode, Backend, FontEntry, Fill, MissingFont, Undecoded, TjSpacing, DecodeSource, render_page, render_page_best_effort};
+use crate::renderstate::RenderState;
 use pathfinder_content::{
     outline::Outline,
     fill::FillRule,
@@ -8,13 +9,14 @@ use pathfinder_geometry::{
     transform2d::Transform2F,
     vector::Vector2F,
 };
-use pdf::object::{Ref, XObject, ImageXObject, Resolve, Resources, MaybeRef};
+use pdf::object::{Ref, XObject, ImageXObject, Resolve, Resources, MaybeRef, Page, PageRc};
 use font::Glyph;
-use pdf::font::Font as PdfFont;
+use pdf::font::{Font as PdfFont, FontType};
 use pdf::error::PdfError;
+use pdf::content::Op;
 use std::sync::Arc;
 use std::path::PathBuf;
-use crate::font::{load_font, StandardCache};
+use crate::font::{load_font, FontOverrides, StandardCache};
 use globalcache::sync::SyncCache;
 use crate::backend::Stroke;
 
@@ -22,36 +24,180 @@ pub struct Tracer<'a> {
     items: Vec<DrawItem>,
     view_box: RectF,
     cache: &'a TraceCache,
+    missing_font: MissingFont,
+    undecoded: Undecoded,
+    tj_spacing: TjSpacing,
 }
+// Fonts loaded while tracing. They are cached by the address of their dictionary, which is
+// only unique while its file is open, so a TraceCache belongs to one file. The standard fonts
+// can be shared between files by giving each cache a clone of one StandardCache.
 pub struct TraceCache {
     fonts: Arc<SyncCache<usize, Option<Arc<FontEntry>>>>,
     std: StandardCache,
+    overrides: FontOverrides,
 }
 impl TraceCache {
     pub fn new() -> Self {
         let standard_fonts = PathBuf::from(".");
 
+        TraceCache::with_standard_fonts(StandardCache::new(standard_fonts))
+    }
+    pub fn with_standard_fonts(std: StandardCache) -> Self {
         TraceCache {
             fonts: SyncCache::new(),
-            std: StandardCache::new(standard_fonts),
+            std,
+            overrides: FontOverrides::new(),
         }
     }
+    // decode some codes as given, see FontOverrides
+    pub fn with_overrides(mut self, overrides: FontOverrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+}
+// how the text of a page is decoded, see the Backend methods of the same names
+#[derive(Clone, Copy, Debug)]
+pub struct TextOptions {
+    pub missing_font: MissingFont,
+    pub undecoded: Undecoded,
+    pub tj_spacing: TjSpacing,
+    // skip an operator that fails instead of giving up on the page, like render_page_best_effort
+    pub keep_going: bool,
 }
+impl Default for TextOptions {
+    fn default() -> Self {
+        TextOptions {
+            missing_font: MissingFont::Drop,
+            undecoded: Undecoded::Drop,
+            tj_spacing: TjSpacing::Relative,
+            keep_going: false,
+        }
+    }
+}
+
 impl<'a> Tracer<'a> {
     pub fn new(cache: &'a TraceCache) -> Self {
+        Tracer::with_options(cache, &TextOptions::default())
+    }
+    pub fn with_options(cache: &'a TraceCache, options: &TextOptions) -> Self {
         Tracer {
             items: vec![],
             view_box: RectF::new(Vector2F::zero(), Vector2F::zero()),
-            cache
+            cache,
+            missing_font: options.missing_font,
+            undecoded: options.undecoded,
+            tj_spacing: options.tj_spacing,
         }
     }
+    pub fn set_missing_font(&mut self, missing_font: MissingFont) {
+        self.missing_font = missing_font;
+    }
+    pub fn set_undecoded(&mut self, undecoded: Undecoded) {
+        self.undecoded = undecoded;
+    }
+    pub fn set_tj_spacing(&mut self, tj_spacing: TjSpacing) {
+        self.tj_spacing = tj_spacing;
+    }
     pub fn view_box(&self) -> RectF {
         self.view_box
     }
     pub fn finish(self) -> Vec<DrawItem> {
         self.items
     }
+    // only the text, in the order it was drawn
+    pub fn text_spans(self) -> Vec<TextSpan> {
+        self.items.into_iter().filter_map(|item| match item {
+            DrawItem::Text(span) => Some(span),
+            _ => None,
+        }).collect()
+    }
+    // the text drawn so far, leaving the other items
+    fn take_text_spans(&mut self) -> Vec<TextSpan> {
+        let (text, rest): (Vec<_>, Vec<_>) = std::mem::take(&mut self.items).into_iter()
+            .partition(|item| matches!(item, DrawItem::Text(_)));
+        self.items = rest;
+        text.into_iter().filter_map(|item| match item {
+            DrawItem::Text(span) => Some(span),
+            _ => None,
+        }).collect()
+    }
+}
+
+// Takes the operators of a page one at a time and returns the text each draws, for callers
+// that get them from elsewhere or only want some of them. Several spans come from a TJ
+// with spaces in it or a form, none from most operators.
+pub struct TextExtractor<'a, 'c, R: Resolve> {
+    state: RenderState<'a, R, Tracer<'c>>,
+}
+impl<'a, 'c, R: Resolve> TextExtractor<'a, 'c, R> {
+    // `transform` maps the user space of the page to device space, see page_text_spans
+    pub fn new(tracer: &'a mut Tracer<'c>, resolve: &'a R, resources: &'a Resources, transform: Transform2F) -> Self {
+        TextExtractor {
+            state: RenderState::new(tracer, resolve, resources, transform),
+        }
+    }
+    pub fn push_op(&mut self, op: &'a Op) -> Result<Vec<TextSpan>, PdfError> {
+        self.state.draw_op(op)?;
+        Ok(self.state.backend().take_text_spans())
+    }
+}
+
+// the text of a page and where it is, see page_text_spans
+pub struct PageTextSpans {
+    pub view_box: RectF,
+    // the transform from the user space of the page to device space
+    pub transform: Transform2F,
+    pub spans: Vec<TextSpan>,
+    // what was skipped with keep_going
+    pub errors: Vec<PdfError>,
+}
+
+// positioned text of a page, for callers that do their own layout analysis
+pub fn page_text_spans(resolve: &impl Resolve, page: &PageRc, cache: &TraceCache, options: &TextOptions) -> Result<PageTextSpans, PdfError> {
+    let mut tracer = Tracer::with_options(cache, options);
+    let (transform, errors) = match options.keep_going {
+        true => render_page_best_effort(&mut tracer, resolve, page, Transform2F::default()),
+        false => (render_page(&mut tracer, resolve, page, Transform2F::default())?, vec![]),
+    };
+    Ok(PageTextSpans { view_box: tracer.view_box(), transform, spans: tracer.text_spans(), errors })
+}
+// a font in the resources of a page and how its text is decoded
+#[derive(Debug)]
+pub struct FontSummary {
+    // what the content stream calls it, like F1
+    pub resource: String,
+    pub name: Option<String>,
+    pub subtype: FontType,
+    pub embedded: bool,
+    // None when the font can't be loaded, its text is then handled by MissingFont
+    pub decode_source: Option<DecodeSource>,
+}
+
+// The fonts in the resources of a page, by resource name, loaded like they are for its text.
+// Fonts only used by forms on the page are in their own resources and not listed.
+pub fn page_fonts(resolve: &impl Resolve, page: &Page, cache: &TraceCache) -> Result<Vec<FontSummary>, PdfError> {
+    let resources = t!(page.resources());
+    let mut tracer = Tracer::new(cache);
+    let mut fonts: Vec<FontSummary> = resources.fonts.iter().map(|(resource, font)| {
+        let decode_source = match tracer.get_font(font, resolve) {
+            Ok(entry) => entry.map(|e| e.decode_source),
+            Err(e) => {
+                warn!("failed to load font {}: {:?}", resource.as_str(), e);
+                None
+            }
+        };
+        FontSummary {
+            resource: resource.as_str().into(),
+            name: font.name.as_ref().map(|name| name.as_str().into()),
+            subtype: font.subtype,
+            embedded: font.embedded_data(resolve).is_some(),
+            decode_source,
+        }
+    }).collect();
+    fonts.sort_by(|a, b| a.resource.cmp(&b.resource));
+    Ok(fonts)
 }
+
 impl<'a> Backend for Tracer<'a> {
     fn set_clip_path(&mut self, path: Option<&Outline>) {
         // self.items.push(DrawItem::ClipPath(path.cloned()));
@@ -95,7 +241,7 @@ impl<'a> Backend for Tracer<'a> {
     fn get_font(&mut self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError> {
         let mut error = None;
         let val = self.cache.fonts.get(&**font_ref as *const PdfFont as usize, || 
-            match load_font(font_ref, resolve, &self.cache.std) {
+            match load_font(font_ref, resolve, &self.cache.std, &self.cache.overrides) {
                 Ok(Some(f)) => Some(Arc::new(f)),
                 Ok(None) => None,
                 Err(e) => {
@@ -112,6 +258,15 @@ impl<'a> Backend for Tracer<'a> {
     fn add_text(&mut self, span: TextSpan) {
         self.items.push(DrawItem::Text(span));
     }
+    fn missing_font(&self) -> MissingFont {
+        self.missing_font
+    }
+    fn undecoded(&self) -> Undecoded {
+        self.undecoded
+    }
+    fn tj_spacing(&self) -> TjSpacing {
+        self.tj_spacing
+    }
 }
 
 #[derive(Debug)]
@@ -141,3 +296,229 @@ pub struct VectorPath {
     pub stroke: Option<(Fill, f32, Stroke)>,
     pub transform: Transform2F,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testpdf;
+    use pdf::file::File;
+    use pdf::content::Point;
+    use pdf::primitive::PdfString;
+
+    #[test]
+    fn text_options() {
+        // no font F9 and no form X9
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << >> /Contents 4 0 R >>",
+            &[&testpdf::stream(b"BT /F9 10 Tf 10 10 Td (AB) Tj ET /X9 Do BT /F9 10 Tf 10 50 Td (C) Tj ET")],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let cache = TraceCache::new();
+        assert!(page_text_spans(&file, &page, &cache, &TextOptions::default()).is_err());
+
+        let options = TextOptions { missing_font: MissingFont::Placeholder, keep_going: true, ..TextOptions::default() };
+        let text = page_text_spans(&file, &page, &cache, &options).unwrap();
+        let spans: Vec<&str> = text.spans.iter().map(|span| span.text.as_str()).collect();
+        std::assert_eq!(spans, ["\u{FFFD}\u{FFFD}", "\u{FFFD}"]);
+        std::assert_eq!(text.errors.len(), 1);
+    }
+
+    #[test]
+    fn lines_by_next_line() {
+        // lines of different lengths, each starting where the one before it started
+        let [type3, to_unicode] = testpdf::abc_font(4);
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> >> /Contents 6 0 R >>",
+            &[
+                &type3,
+                &to_unicode,
+                &testpdf::stream(b"BT /F1 10 Tf 12 TL 10 100 Td (ABC) Tj T* (A) Tj T* (BC) Tj ET"),
+            ],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let spans = page_text_spans(&file, &page, &TraceCache::new(), &TextOptions::default()).unwrap().spans;
+        let lines: Vec<(&str, f32, f32)> = spans.iter().map(|span| (span.text.as_str(), span.rect.min_x(), span.rect.max_y())).collect();
+        std::assert_eq!(lines.len(), 3);
+        for (text, x, _) in lines.iter() {
+            assert!((x - lines[0].1).abs() < 1e-4, "{} starts at {}", text, x);
+        }
+        // each a leading of 12 below the one before it, in mm
+        assert!((lines[1].2 - lines[0].2 - 12. * 25.4 / 72.).abs() < 1e-3);
+        assert!((lines[2].2 - lines[1].2 - 12. * 25.4 / 72.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn ops_one_at_a_time() {
+        let [type3, to_unicode] = testpdf::abc_font(4);
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> >> >>",
+            &[
+                &type3,
+                &to_unicode,
+            ],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let resources = page.resources().unwrap();
+        let text = |s: &[u8]| Op::TextDraw { text: PdfString::new(s.into()) };
+        let ops = [
+            Op::BeginText,
+            Op::TextFont { name: "F1".into(), size: 10. },
+            Op::MoveTextPosition { translation: Point { x: 10., y: 10. } },
+            text(b"AB"),
+            text(b"C"),
+            Op::EndText,
+        ];
+
+        let cache = TraceCache::new();
+        let mut tracer = Tracer::new(&cache);
+        let mut extractor = TextExtractor::new(&mut tracer, &file, resources, Transform2F::default());
+        // the spans of each operator
+        let text: Vec<String> = ops.iter()
+            .map(|op| extractor.push_op(op).unwrap().into_iter().map(|span| span.text).collect::<Vec<_>>().join("|"))
+            .collect();
+        std::assert_eq!(text, ["", "", "", "AB", "C", ""]);
+    }
+
+    #[test]
+    fn best_effort() {
+        // there is no form X9
+        let [type3, to_unicode] = testpdf::abc_font(4);
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> >> /Contents 6 0 R >>",
+            &[
+                &type3,
+                &to_unicode,
+                &testpdf::stream(b"BT /F1 10 Tf 10 10 Td (AB) Tj ET /X9 Do BT /F1 10 Tf 10 50 Td (C) Tj ET"),
+            ],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let cache = TraceCache::new();
+        let mut tracer = Tracer::new(&cache);
+        assert!(crate::render_page(&mut tracer, &file, &page, Transform2F::default()).is_err());
+
+        let mut tracer = Tracer::new(&cache);
+        let (_, errors) = crate::render_page_best_effort(&mut tracer, &file, &page, Transform2F::default());
+        let spans: Vec<String> = tracer.text_spans().into_iter().map(|span| span.text).collect();
+        // the text around what failed, which is reported
+        std::assert_eq!(spans, ["AB", "C"]);
+        std::assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn unicode_overrides() {
+        // the ToUnicode of the font has B as C
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> >> /Contents 6 0 R >>",
+            &[
+                b"<< /Type /Font /Subtype /Type3 /Name /Broken /FontBBox [0 0 1000 1000] /FontMatrix [0.001 0 0 0.001 0 0] /CharProcs << >> /FirstChar 65 /LastChar 66 /Widths [500 500] /ToUnicode 5 0 R >>",
+                &testpdf::stream(b"
+                    begincmap
+                    1 begincodespacerange <00> <FF> endcodespacerange
+                    2 beginbfchar <41> <0041> <42> <0043> endbfchar
+                    endcmap
+                "),
+                &testpdf::stream(b"BT /F1 10 Tf 10 10 Td (AB) Tj ET"),
+            ],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let text = |cache: &TraceCache| page_text_spans(&file, &page, cache, &TextOptions::default()).unwrap().spans[0].text.clone();
+        std::assert_eq!(text(&TraceCache::new()), "AC");
+
+        let mut overrides = FontOverrides::new();
+        overrides.add("Broken", 0x42, "B");
+        std::assert_eq!(text(&TraceCache::new().with_overrides(overrides)), "AB");
+    }
+
+    #[test]
+    fn missing_fonts() {
+        // there is no font F9
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << >> /Contents 4 0 R >>",
+            &[&testpdf::stream(b"BT /F9 10 Tf 10 10 Td (Hi!) Tj ET")],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let cache = TraceCache::new();
+        let text = |missing_font| {
+            let options = TextOptions { missing_font, ..TextOptions::default() };
+            page_text_spans(&file, &page, &cache, &options).unwrap().spans.into_iter().map(|span| span.text).collect::<Vec<_>>().concat()
+        };
+        std::assert_eq!(text(MissingFont::Drop), "");
+        std::assert_eq!(text(MissingFont::Placeholder), "\u{FFFD}\u{FFFD}\u{FFFD}");
+        std::assert_eq!(text(MissingFont::Guess), "Hi!");
+    }
+
+    #[test]
+    fn lossy() {
+        // the ToUnicode has no B
+        let [type3, to_unicode] = testpdf::abc_font_mapping(4, "2 beginbfchar <41> <0041> <43> <0043> endbfchar");
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R >> >> /Contents 6 0 R >>",
+            &[
+                &type3,
+                &to_unicode,
+                &testpdf::stream(b"BT /F1 10 Tf 10 10 Td (ABC) Tj 0 20 Td (CAB) Tj ET"),
+            ],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let cache = TraceCache::new();
+        let text = |undecoded| {
+            let options = TextOptions { undecoded, ..TextOptions::default() };
+            page_text_spans(&file, &page, &cache, &options).unwrap().spans.into_iter().map(|span| span.text).collect::<Vec<_>>()
+        };
+        std::assert_eq!(text(Undecoded::Drop), ["AC", "CA"]);
+        std::assert_eq!(text(Undecoded::Replacement), ["A\u{FFFD}C", "CA\u{FFFD}"]);
+        // the code that didn't decode
+        std::assert_eq!(text(Undecoded::Hex), ["A<42>C", "CA<42>"]);
+        std::assert_eq!(text(Undecoded::PrivateUse), ["A\u{F0042}C", "CA\u{F0042}"]);
+    }
+
+    #[test]
+    fn decode_sources() {
+        // F2 has neither a ToUnicode nor an encoding, its codes are guessed at
+        let [type3, to_unicode] = testpdf::abc_font(4);
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F1 4 0 R /F2 6 0 R >> >> /Contents 7 0 R >>",
+            &[
+                &type3,
+                &to_unicode,
+                b"<< /Type /Font /Subtype /Type3 /FontBBox [0 0 1000 1000] /FontMatrix [0.001 0 0 0.001 0 0] /CharProcs << >> /FirstChar 65 /LastChar 67 /Widths [500 500 500] >>",
+                &testpdf::stream(b"BT /F1 10 Tf 10 10 Td (AB) Tj /F2 10 Tf 0 20 Td (AB) Tj ET"),
+            ],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let options = TextOptions { undecoded: Undecoded::Hex, ..TextOptions::default() };
+        let spans = page_text_spans(&file, &page, &TraceCache::new(), &options).unwrap().spans;
+        let sources: Vec<Option<DecodeSource>> = spans.iter().map(|span| span.decode_source()).collect();
+        std::assert_eq!(sources, [Some(DecodeSource::ToUnicode), Some(DecodeSource::RawGuess)]);
+    }
+
+    #[test]
+    fn fonts_of_a_page() {
+        let data = testpdf::pdf_with_page(
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 200] /Resources << /Font << /F2 7 0 R /F1 4 0 R >> >> >>",
+            &[
+                b"<< /Type /Font /Subtype /TrueType /BaseFont /Embedded /FirstChar 32 /LastChar 32 /Widths [250] /FontDescriptor 5 0 R >>",
+                b"<< /Type /FontDescriptor /FontName /Embedded /Flags 32 /FontBBox [0 -200 1000 800] /ItalicAngle 0 /FontFile2 6 0 R >>",
+                &testpdf::stream(b"not a font program"),
+                b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>",
+            ],
+        );
+        let file = File::from_data(data).unwrap();
+        let page = file.get_page(0).unwrap();
+        let fonts = page_fonts(&file, &page, &TraceCache::new()).unwrap();
+        // the subtype as --list-fonts prints it
+        let fonts: Vec<_> = fonts.iter().map(|f| (f.resource.as_str(), f.name.as_deref(), format!("{:?}", f.subtype), f.embedded)).collect();
+        std::assert_eq!(fonts, [
+            ("F1", Some("Embedded"), "TrueType".into(), true),
+            ("F2", Some("Helvetica"), "Type1".into(), false),
+        ]);
+    }
+}